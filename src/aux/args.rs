@@ -1,23 +1,98 @@
 use ::docopt::Docopt;
+use ::prepare::transform::HiddenLines;
+use ::render::Format;
+use super::config::Book;
+use std::collections::HashMap;
 
 const USAGE: &str = indoc!(r#"
     Compile Rustbook to EBook formats.
 
     Usage:
-    compile-trpl [--prefix PREFIX] [--source DIRECTORY] [--meta FILE]
+    compile-trpl [--prefix PREFIX] [--source DIRECTORY] [--meta FILE] [--date DATE | --git-date] [--out-dir DIR] [--output-name NAME] [--number-sections] [--check | --links [--mirror DIR] [--allowlist FILE] | [--pandoc] [--all | --format FORMAT...]]
+    compile-trpl --config FILE [--date DATE | --git-date] [--out-dir DIR] [--output-name NAME] [--number-sections] [--check | --links [--mirror DIR] [--allowlist FILE] | [--pandoc] [--all | --format FORMAT...]]
+    compile-trpl test [--prefix PREFIX] [--source DIRECTORY] [--out-dir DIR] [--toolchain NAME] [--edition EDITION]
+    compile-trpl test --config FILE [--out-dir DIR] [--toolchain NAME] [--edition EDITION]
 
     Options:
     -h, --help          Show this message
     --prefix PREFIX     Book prefix/short name [default: trpl]
     --source DIRECTORY  Book markdown directory [default: trpl]
-    --meta FILE         Metadata, must contain `date: {release_date}` [default: trpl_meta.yml]
+    --meta FILE         Metadata, `{release_date}` is replaced by the release date [default: trpl_meta.yml]
+    --config FILE       Build all books described in a books.toml
+    --date DATE         Release date as YYYY-MM-DD, overrides SOURCE_DATE_EPOCH
+    --git-date          Use the date of the last commit touching the source directory
+    --out-dir DIR       Where rendered books are written [default: dist]
+    --output-name NAME  File name template, may use {prefix}, {date}, {ext} and
+                        {version}, the version of this tool
+                        [default: {prefix}-{date}.{ext}]
+    --number-sections   Put section numbers like 3.2.1 in front of chapter headings
+    --check             Report links to missing chapters or anchors instead
+                        of rendering, failing if there are any
+    --links             List the external links instead of rendering
+    --mirror DIR        Only list links missing in a `wget --mirror` of the
+                        linked sites, failing if there are any
+    --allowlist FILE    Only list links missing in a file of known URLs, one
+                        per line, `*` at the end allows every URL starting with it
+    --pandoc            Render through Pandoc instead of the native writers
+    --format FORMAT     Output format to render, repeatable. One of html, epub,
+                        markdown, site, latex, pdf, mobi. Defaults to the
+                        formats of the config, or markdown, epub and html
+    --all               Render all output formats
+    --toolchain NAME    Test the examples with this rustup toolchain, like
+                        `stable` or `1.30.0`, instead of the default one
+    --edition EDITION   Rust edition the examples are tested in
     "#);
 
 #[derive(Deserialize, Debug)]
 pub struct Args {
+    pub cmd_test: bool,
     pub flag_prefix: String,
     pub flag_source: String,
     pub flag_meta:   String,
+    pub flag_config: Option<String>,
+    pub flag_date:   Option<String>,
+    pub flag_git_date: bool,
+    pub flag_out_dir: String,
+    pub flag_output_name: String,
+    pub flag_number_sections: bool,
+    pub flag_check: bool,
+    pub flag_links: bool,
+    pub flag_mirror: Option<String>,
+    pub flag_allowlist: Option<String>,
+    pub flag_pandoc: bool,
+    pub flag_format: Vec<Format>,
+    pub flag_all:    bool,
+    pub flag_toolchain: Option<String>,
+    pub flag_edition: Option<String>,
+}
+
+impl Args {
+    /// The formats asked for on the command line, if any
+    pub fn formats(&self) -> Option<Vec<Format>> {
+        if self.flag_all { return Some(Format::ALL.to_vec()) }
+        if self.flag_format.is_empty() { return None }
+
+        let mut formats = Vec::with_capacity(self.flag_format.len());
+        for format in &self.flag_format {
+            if !formats.contains(format) { formats.push(*format) }
+        }
+        Some(formats)
+    }
+
+    /// The single book described by `--prefix`, `--source` and `--meta`
+    pub fn book(&self) -> Book {
+        Book {
+            prefix: self.flag_prefix.clone(),
+            source: self.flag_source.clone(),
+            meta: self.flag_meta.clone(),
+            formats: None,
+            stylesheets: HashMap::new(),
+            code_widths: HashMap::new(),
+            themes: HashMap::new(),
+            transforms: None,
+            hidden_lines: HiddenLines::default(),
+        }
+    }
 }
 
 pub fn get() -> Args {
@@ -32,7 +107,7 @@ mod tests {
 
     #[test]
     fn docopt_parsing() {
-        let input = ["trpl-ebook", "--prefix=foo", "--source=bar", "--meta=baz"].into_iter();
+        let input = ["trpl-ebook", "--prefix=foo", "--source=bar", "--meta=baz", "--date=2018-05-10", "--pandoc"].into_iter();
         let args: Args = Docopt::new(USAGE)
             .and_then(|d| d.argv(input).deserialize())
             .unwrap();
@@ -43,6 +118,8 @@ mod tests {
         assert_eq!("foo", args.flag_prefix);
         assert_eq!("bar", args.flag_source);
         assert_eq!("baz", args.flag_meta  );
+        assert_eq!(Some("2018-05-10".to_string()), args.flag_date);
+        assert!(args.flag_pandoc);
     }
 
     #[test]
@@ -58,5 +135,67 @@ mod tests {
         assert_eq!("trpl",          args.flag_prefix);
         assert_eq!("trpl",          args.flag_source);
         assert_eq!("trpl_meta.yml", args.flag_meta  );
+        assert_eq!(None, args.flag_date);
+        assert!(!args.flag_git_date);
+        assert_eq!("dist", args.flag_out_dir);
+        assert_eq!("{prefix}-{date}.{ext}", args.flag_output_name);
+        assert!(!args.flag_pandoc);
+        assert!(!args.flag_number_sections);
+        assert!(!args.flag_check);
+        assert_eq!(None, args.flag_config);
+        assert_eq!(None, args.formats());
+    }
+
+    #[test]
+    fn docopt_formats() {
+        let input = ["trpl-ebook", "--format=pdf", "--format=Kindle", "--format=pdf"].into_iter();
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(input).deserialize())
+            .unwrap();
+        assert_eq!(Some(vec![Format::Pdf, Format::Mobi]), args.formats());
+
+        let input = ["trpl-ebook", "--all"].into_iter();
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(input).deserialize())
+            .unwrap();
+        assert_eq!(Some(Format::ALL.to_vec()), args.formats());
+
+        let input = ["trpl-ebook", "--config=books.toml", "--format=epub"].into_iter();
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(input).deserialize())
+            .unwrap();
+        assert_eq!(Some("books.toml".to_string()), args.flag_config);
+        assert_eq!(Some(vec![Format::Epub]), args.formats());
+
+        let input = ["trpl-ebook", "--format=docx"].into_iter();
+        assert!(Docopt::new(USAGE).and_then(|d| d.argv(input).deserialize::<Args>()).is_err());
+
+        let input = ["trpl-ebook", "--config=books.toml", "--check"].into_iter();
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(input).deserialize())
+            .unwrap();
+        assert!(args.flag_check);
+
+        let input = ["trpl-ebook", "--links", "--mirror=mirror", "--allowlist=links.txt"].into_iter();
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(input).deserialize())
+            .unwrap();
+        assert!(args.flag_links);
+        assert_eq!(Some("mirror".to_string()), args.flag_mirror);
+        assert_eq!(Some("links.txt".to_string()), args.flag_allowlist);
+
+        let input = ["trpl-ebook", "--check", "--format=epub"].into_iter();
+        assert!(Docopt::new(USAGE).and_then(|d| d.argv(input).deserialize::<Args>()).is_err());
+
+        let input = ["trpl-ebook", "test", "--config=books.toml", "--toolchain=1.30.0", "--edition=2018"].into_iter();
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.argv(input).deserialize())
+            .unwrap();
+        assert!(args.cmd_test);
+        assert_eq!(Some("1.30.0".to_string()), args.flag_toolchain);
+        assert_eq!(Some("2018".to_string()), args.flag_edition);
+
+        let input = ["trpl-ebook", "test", "--format=epub"].into_iter();
+        assert!(Docopt::new(USAGE).and_then(|d| d.argv(input).deserialize::<Args>()).is_err());
     }
 }
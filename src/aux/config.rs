@@ -0,0 +1,259 @@
+//! The `books.toml` manifest, describing every book and omnibus volume to build
+
+use super::file;
+use failure::Error;
+use ::prepare::transform::HiddenLines;
+use ::render::Format;
+use std::{
+    collections::HashMap,
+    path::Path,
+    str::FromStr,
+};
+
+/// One book to compile
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Book {
+    /// Book short name, used for output names
+    pub prefix: String,
+    /// Directory containing `SUMMARY.md` and `README.md`
+    pub source: String,
+    /// Metadata file prepended to the book
+    pub meta: String,
+    /// Formats to render unless given on the command line
+    #[serde(default)]
+    pub formats: Option<Vec<Format>>,
+    /// Stylesheets replacing the default ones of a format
+    #[serde(default)]
+    pub stylesheets: HashMap<Format, String>,
+    /// Widths code lines are wrapped at, replacing a format's default
+    #[serde(default)]
+    pub code_widths: HashMap<Format, usize>,
+    /// Highlighting themes from `lib/{name}.theme`, replacing a format's default
+    #[serde(default)]
+    pub themes: HashMap<Format, String>,
+    /// Names of the passes chapters go through, in order,
+    /// instead of the built-in ones
+    #[serde(default)]
+    pub transforms: Option<Vec<String>>,
+    /// What the `code-blocks` pass does with hidden lines of Rust examples
+    #[serde(default)]
+    pub hidden_lines: HiddenLines,
+}
+
+/// Several books compiled into one volume, each book becoming a part
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Omnibus {
+    /// Volume short name, used for output names
+    pub prefix: String,
+    pub title: String,
+    /// Metadata file replacing the metadata combined from the books
+    #[serde(default)]
+    pub meta: Option<String>,
+    /// Prefixes of the included books, in order
+    pub books: Vec<String>,
+    #[serde(default)]
+    pub formats: Option<Vec<Format>>,
+    #[serde(default)]
+    pub stylesheets: HashMap<Format, String>,
+    #[serde(default)]
+    pub code_widths: HashMap<Format, usize>,
+    #[serde(default)]
+    pub themes: HashMap<Format, String>,
+}
+
+/// A chapter pass run by an external program, for books to list by `name`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ExternalTransform {
+    pub name: String,
+    /// Program and arguments
+    pub command: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(rename = "book")]
+    pub books: Vec<Book>,
+    #[serde(rename = "omnibus", default)]
+    pub omnibuses: Vec<Omnibus>,
+    #[serde(rename = "transform", default)]
+    pub transforms: Vec<ExternalTransform>,
+}
+
+impl FromStr for Config {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let config: Config = ::toml::from_str(source)?;
+
+        let prefixes: Vec<&str> = config.books.iter().map(|book| book.prefix.as_str())
+            .chain(config.omnibuses.iter().map(|omnibus| omnibus.prefix.as_str()))
+            .collect();
+        for (position, prefix) in prefixes.iter().enumerate() {
+            if prefixes[..position].contains(prefix) {
+                bail!("Book prefix '{}' is used more than once", prefix);
+            }
+        }
+
+        for omnibus in &config.omnibuses {
+            if omnibus.books.is_empty() {
+                bail!("Omnibus '{}' includes no books", omnibus.prefix);
+            }
+            config.books_of(omnibus)?;
+        }
+
+        for transform in &config.transforms {
+            if transform.command.is_empty() {
+                bail!("Transform '{}' has no command", transform.name);
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let source = file::to_string(path)
+            .map_err(|e| format_err!("Failed to read config {}: {}", path.display(), e))?;
+
+        source
+            .parse()
+            .map_err(|e| format_err!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// The books included in `omnibus`, in its order
+    pub fn books_of(&self, omnibus: &Omnibus) -> Result<Vec<&Book>, Error> {
+        omnibus.books
+            .iter()
+            .map(|prefix| {
+                self.books
+                    .iter()
+                    .find(|book| &book.prefix == prefix)
+                    .ok_or_else(|| format_err!("Omnibus '{}' includes unknown book '{}'", omnibus.prefix, prefix))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Config, Error> { source.parse() }
+
+    #[test]
+    fn books_config() {
+        let config = parse(indoc!(r#"
+            [[book]]
+            prefix = "trpl"
+            source = "trpl"
+            meta = "trpl_meta.yml"
+            formats = ["epub", "pdf"]
+
+            [book.stylesheets]
+            html = "custom.css"
+
+            [book.code_widths]
+            epub = 72
+
+            [book.themes]
+            html = "breezedark"
+
+            [[book]]
+            prefix = "nomicon"
+            source = "nomicon"
+            meta = "nomicon_meta.yml"
+            transforms = ["glossary", "links", "headings", "references", "normalize"]
+            hidden_lines = "toggle"
+
+            [[transform]]
+            name = "glossary"
+            command = ["./glossary-links", "--terms=glossary.toml"]
+            "#)).unwrap();
+
+        assert_eq!(2, config.books.len());
+        assert_eq!(Some(vec![Format::Epub, Format::Pdf]), config.books[0].formats);
+        assert_eq!(Some(&"custom.css".to_string()), config.books[0].stylesheets.get(&Format::Html));
+        assert_eq!(Some(&72), config.books[0].code_widths.get(&Format::Epub));
+        assert_eq!(Some(&"breezedark".to_string()), config.books[0].themes.get(&Format::Html));
+        assert_eq!(None, config.books[1].formats);
+        assert_eq!(None, config.books[0].transforms);
+        assert_eq!(Some("glossary"), config.books[1].transforms.as_ref().map(|names| names[0].as_str()));
+        assert_eq!(vec!["./glossary-links", "--terms=glossary.toml"], config.transforms[0].command);
+        assert_eq!(HiddenLines::Remove, config.books[0].hidden_lines);
+        assert_eq!(HiddenLines::Toggle, config.books[1].hidden_lines);
+
+        assert!(parse("[[book]]\nprefix = \"trpl\"\n").is_err());
+        assert!(parse(indoc!(r#"
+            [[book]]
+            prefix = "trpl"
+            source = "trpl"
+            meta = "trpl_meta.yml"
+            formats = ["docx"]
+            "#)).is_err());
+        assert!(parse(indoc!(r#"
+            [[book]]
+            prefix = "trpl"
+            source = "trpl"
+            meta = "trpl_meta.yml"
+            hidden_lines = "fold"
+            "#)).is_err());
+        assert!(parse(&format!("{}{}", BOOKS, indoc!(r#"
+
+            [[transform]]
+            name = "glossary"
+            command = []
+            "#))).is_err());
+    }
+
+    const BOOKS: &str = indoc!(r#"
+        [[book]]
+        prefix = "trpl"
+        source = "trpl"
+        meta = "trpl_meta.yml"
+
+        [[book]]
+        prefix = "nomicon"
+        source = "nomicon"
+        meta = "nomicon_meta.yml"
+        "#);
+
+    #[test]
+    fn omnibus_config() {
+        let config = parse(&format!("{}{}", BOOKS, indoc!(r#"
+
+            [[omnibus]]
+            prefix = "rust-library"
+            title = "The Rust Library"
+            books = ["nomicon", "trpl"]
+            formats = ["epub"]
+            "#))).unwrap();
+
+        let omnibus = &config.omnibuses[0];
+        assert_eq!(None, omnibus.meta);
+        let prefixes: Vec<&str> = config.books_of(omnibus).unwrap().iter().map(|book| book.prefix.as_str()).collect();
+        assert_eq!(vec!["nomicon", "trpl"], prefixes);
+
+        let error = parse(&format!("{}{}", BOOKS, indoc!(r#"
+
+            [[omnibus]]
+            prefix = "rust-library"
+            title = "The Rust Library"
+            books = ["trpl", "reference"]
+            "#))).unwrap_err();
+        assert_eq!("Omnibus 'rust-library' includes unknown book 'reference'", error.to_string());
+
+        assert!(parse(&format!("{}{}", BOOKS, indoc!(r#"
+
+            [[omnibus]]
+            prefix = "trpl"
+            title = "The Rust Library"
+            books = ["trpl"]
+            "#))).is_err());
+    }
+}
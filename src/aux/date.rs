@@ -0,0 +1,128 @@
+//! Release dates, always as ISO 8601 calendar dates (`YYYY-MM-DD`)
+
+use failure::{Error, ResultExt};
+use std::{env, path::Path, process::Command};
+
+mod reg {
+    pub const ISO_DATE: &str = r"^(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})$";
+    pub const META_DATE: &str = r#"(?m)^date:\s*"?(?P<date>\d{4}-\d{2}-\d{2})"?\s*$"#;
+}
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Checks that `date` is a valid ISO date
+pub fn parse(date: &str) -> Result<String, Error> {
+    lazy_static_regex!(ISO_DATE, reg::ISO_DATE);
+
+    let date = date.trim();
+    let captures = ISO_DATE
+        .captures(date)
+        .ok_or_else(|| format_err!("Invalid date '{}', expected YYYY-MM-DD", date))?;
+    let year: u32 = captures["year"].parse()?;
+    let month: u32 = captures["month"].parse()?;
+    let day: u32 = captures["day"].parse()?;
+
+    if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) {
+        bail!("Invalid date '{}', no such day", date);
+    }
+
+    Ok(date.to_string())
+}
+
+/// The UTC date of a Unix timestamp
+pub fn from_timestamp(seconds: i64) -> String {
+    // Howard Hinnant's `civil_from_days`
+    let mut days = seconds / SECONDS_PER_DAY;
+    if seconds % SECONDS_PER_DAY < 0 { days -= 1 }
+    let days = days + 719_468;
+    let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn parse_timestamp(seconds: &str) -> Result<String, Error> {
+    let seconds = seconds.trim();
+    seconds
+        .parse()
+        .map(from_timestamp)
+        .map_err(|_| format_err!("Invalid Unix timestamp '{}'", seconds))
+}
+
+/// The date from `SOURCE_DATE_EPOCH`, see <https://reproducible-builds.org/specs/source-date-epoch/>
+pub fn source_date_epoch() -> Result<Option<String>, Error> {
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(seconds) => parse_timestamp(&seconds)
+            .map(Some)
+            .map_err(|e| format_err!("Invalid SOURCE_DATE_EPOCH: {}", e)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// The date of the last commit touching any of `directories`
+pub fn last_commit<P: AsRef<Path>>(directories: &[P]) -> Result<String, Error> {
+    let output = Command::new("git")
+        .arg("log")
+        .arg("-1")
+        .arg("--format=%ct")
+        .arg("--")
+        .args(directories.iter().map(AsRef::as_ref))
+        .output()
+        .context("Failed to execute git")?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || stdout.trim().is_empty() {
+        let names: Vec<String> = directories.iter().map(|dir| dir.as_ref().display().to_string()).collect();
+        bail!("Found no git commit touching {}", names.join(", "));
+    }
+
+    parse_timestamp(&stdout)
+}
+
+/// A literal `date:` in the metadata, rather than the `{release_date}` placeholder
+pub fn from_meta(meta: &str) -> Option<String> {
+    lazy_static_regex!(META_DATE, reg::META_DATE);
+
+    META_DATE
+        .captures(meta)
+        .and_then(|captures| parse(&captures["date"]).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iso_dates() {
+        assert_eq!("2016-10-01", parse("2016-10-01").unwrap());
+        assert_eq!("2016-02-29", parse("2016-02-29").unwrap());
+        assert!(parse("2015-02-29").is_err());
+        assert!(parse("2016-13-01").is_err());
+        assert!(parse("01.10.2016").is_err());
+
+        assert_eq!("1970-01-01", from_timestamp(0));
+        assert_eq!("2016-10-01", from_timestamp(1_475_323_199));
+        assert_eq!("2000-02-29", from_timestamp(951_782_400));
+    }
+
+    #[test]
+    fn meta_date() {
+        assert_eq!(Some("2018-05-10".to_string()), from_meta("title: x\ndate: \"2018-05-10\"\n"));
+        assert_eq!(None, from_meta("title: x\ndate: {release_date}\n"));
+    }
+}
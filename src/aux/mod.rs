@@ -1,3 +1,5 @@
 #[macro_use] pub mod regex;
 pub mod file;
 pub mod args;
+pub mod config;
+pub mod date;
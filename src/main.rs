@@ -1,59 +1,366 @@
 extern crate regex;
 extern crate rayon;
 extern crate docopt;
+extern crate pulldown_cmark;
+extern crate zip;
 extern crate serde;
+extern crate toml;
+extern crate serde_yaml;
 #[macro_use] extern crate failure;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate lazy_static;
 #[macro_use] extern crate indoc;
 
 #[macro_use] mod aux;
+mod metadata;
 mod prepare;
 mod render;
 
-use std::process;
+use aux::{
+    args::Args,
+    config::{Book, Config, Omnibus},
+};
+use prepare::{
+    links::{Allowlist, Link},
+    transform::{External, Registry},
+    Prepared,
+};
+use render::Format;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::Path,
+    process,
+};
 
 use rayon::iter::{
     ParallelIterator,
     IntoParallelRefIterator
 };
 
-const RELEASE_DATE: &str = "2016-10-01";
-
 fn err_exit(error: &failure::Error) -> ! {
-    eprintln!("Failed to create book:\n{}", error.backtrace());
+    eprintln!("Failed to create book: {}\n{}", error, error.backtrace());
     process::exit(1);
 }
 
+/// A single book or an omnibus of several books
+enum Volume<'a> {
+    Book(&'a Book),
+    Omnibus(&'a Omnibus, Vec<&'a Book>),
+}
+
+impl<'a> Volume<'a> {
+    fn prefix(&self) -> &str {
+        match self {
+            Volume::Book(book) => &book.prefix,
+            Volume::Omnibus(omnibus, _) => &omnibus.prefix,
+        }
+    }
+
+    fn formats(&self) -> Option<Vec<Format>> {
+        match self {
+            Volume::Book(book) => book.formats.clone(),
+            Volume::Omnibus(omnibus, _) => omnibus.formats.clone(),
+        }
+    }
+
+    fn stylesheets(&self) -> &HashMap<Format, String> {
+        match self {
+            Volume::Book(book) => &book.stylesheets,
+            Volume::Omnibus(omnibus, _) => &omnibus.stylesheets,
+        }
+    }
+
+    fn code_widths(&self) -> &HashMap<Format, usize> {
+        match self {
+            Volume::Book(book) => &book.code_widths,
+            Volume::Omnibus(omnibus, _) => &omnibus.code_widths,
+        }
+    }
+
+    fn themes(&self) -> &HashMap<Format, String> {
+        match self {
+            Volume::Book(book) => &book.themes,
+            Volume::Omnibus(omnibus, _) => &omnibus.themes,
+        }
+    }
+
+    fn sources(&self) -> Vec<&str> {
+        match self {
+            Volume::Book(book) => vec![&book.source],
+            Volume::Omnibus(_, books) => books.iter().map(|book| book.source.as_str()).collect(),
+        }
+    }
+
+    /// The omnibus' own metadata file comes first
+    fn metas(&self) -> Vec<&str> {
+        match self {
+            Volume::Book(book) => vec![&book.meta],
+            Volume::Omnibus(omnibus, books) => omnibus.meta
+                .iter()
+                .map(String::as_str)
+                .chain(books.iter().map(|book| book.meta.as_str()))
+                .collect(),
+        }
+    }
+
+    fn create(&self, release_date: &str, number_sections: bool, registry: &Registry) -> Result<Prepared, failure::Error> {
+        match self {
+            Volume::Book(book) => prepare::create_book(
+                &book.source,
+                &book.meta,
+                release_date,
+                number_sections,
+                &registry.pipeline(book.transforms.as_ref().map(Vec::as_slice))?,
+                book.hidden_lines
+            ),
+            Volume::Omnibus(omnibus, books) => prepare::create_omnibus(
+                &omnibus.title,
+                omnibus.meta.as_ref().map(String::as_str),
+                books,
+                release_date,
+                number_sections,
+                registry
+            ),
+        }
+    }
+}
+
+/// In order of precedence: `--date`, `--git-date`, `SOURCE_DATE_EPOCH`,
+/// a literal date in the metadata, the last commit touching the sources.
+/// Never the day of the build, so that builds are reproducible.
+fn release_date(opt: &Args, volume: &Volume) -> Result<String, failure::Error> {
+    if let Some(ref date) = opt.flag_date {
+        return aux::date::parse(date);
+    }
+    if opt.flag_git_date {
+        return aux::date::last_commit(&volume.sources());
+    }
+    if let Some(date) = aux::date::source_date_epoch()? {
+        return Ok(date);
+    }
+
+    let literal = volume.metas()
+        .iter()
+        .filter_map(|path| aux::file::to_string(path).ok())
+        .filter_map(|meta| aux::date::from_meta(&meta))
+        .next();
+    match literal {
+        Some(date) => Ok(date),
+        None => aux::date::last_commit(&volume.sources())
+            .map_err(|e| format_err!("{}, set the release date with --date", e)),
+    }
+}
+
+fn output_for(opt: &Args, volume: &Volume) -> Result<render::Output, failure::Error> {
+    let mut output = render::Output::new(
+        &opt.flag_out_dir,
+        &opt.flag_output_name,
+        volume.prefix(),
+        &release_date(opt, volume)?
+    )?;
+    output.stylesheets = volume.stylesheets().clone();
+    output.code_widths = volume.code_widths().clone();
+    output.themes = volume.themes().clone();
+    Ok(output)
+}
+
+/// Reports every link to a missing anchor, exiting with an error if there are any
+fn check_links<'a, I>(books: I)
+where
+    I: IntoIterator<Item = (&'a Prepared, &'a str)>,
+{
+    let mut broken = 0;
+
+    for (book, prefix) in books {
+        let anchors = render::anchors(&book.markdown);
+        let dangling = prepare::links::dangling(&book.links, &anchors);
+
+        let internal = book.links.iter().filter(|link| link.is_internal()).count();
+        println!("{}: {} internal links, {} broken", prefix, internal, dangling.len());
+        for link in &dangling {
+            println!("  {}", link);
+        }
+        broken += dangling.len();
+    }
+
+    if broken > 0 {
+        eprintln!("Found {} broken links", broken);
+        process::exit(1);
+    }
+}
+
+/// Lists every external link, or with a `mirror` or `allowlist` only
+/// those that neither knows, exiting with an error if there are any
+fn external_links<'a, I>(books: I, mirror: Option<&Path>, allowlist: Option<&Allowlist>)
+where
+    I: IntoIterator<Item = (&'a Prepared, &'a str)>,
+{
+    let validate = mirror.is_some() || allowlist.is_some();
+    let empty = Allowlist::default();
+    let mut unknown = 0;
+
+    for (book, prefix) in books {
+        let external: Vec<&Link> = book.links.iter().filter(|link| !link.is_internal()).collect();
+
+        if !validate {
+            println!("{}: {} external links", prefix, external.len());
+            for link in &external {
+                println!("  {}", link);
+            }
+            continue;
+        }
+
+        let unverified = prepare::links::unverified(&book.links, mirror, allowlist.unwrap_or(&empty));
+        println!("{}: {} external links, {} unverified", prefix, external.len(), unverified.len());
+        for link in &unverified {
+            if prepare::links::is_mirrorable(&link.target) {
+                println!("  {}", link);
+            } else {
+                println!("  {} (can not be mirrored)", link);
+            }
+        }
+        unknown += unverified.len();
+    }
+
+    if unknown > 0 {
+        eprintln!("Found {} links neither mirrored nor allowed", unknown);
+        process::exit(1);
+    }
+}
+
+/// Tests the Rust examples of every book with rustdoc, exiting with an error if any fail
+fn test_examples(books: &[Book], opt: &Args) {
+    use prepare::examples::{extract_book, run_rustdoc, write_harness};
+
+    let toolchain = opt.flag_toolchain.as_ref().map(String::as_str);
+    let edition = opt.flag_edition.as_ref().map(String::as_str);
+    let mut failed = 0;
+
+    for book in books {
+        let directory = Path::new(&opt.flag_out_dir).join(format!("{}-examples", book.prefix));
+        let chapters = extract_book(Path::new(&book.source)).unwrap_or_else(|e| err_exit(&e));
+        let examples: usize = chapters.iter().map(|chapter| chapter.blocks.len()).sum();
+        println!("Testing {} examples of {} in {}", examples, book.prefix, directory.display());
+
+        let results = chapters
+            .par_iter() // Because rustdoc compiles every example on its own
+            .filter(|chapter| !chapter.blocks.is_empty())
+            .map(|chapter| {
+                let harness = write_harness(chapter, &directory)?;
+                let (passed, report) = run_rustdoc(&harness, toolchain, edition)?;
+                Ok((chapter, passed, report))
+            })
+            .collect::<Result<Vec<_>, failure::Error>>()
+            .unwrap_or_else(|e| err_exit(&e));
+
+        for (chapter, passed, report) in results {
+            if passed {
+                println!("  {}: ok", chapter.filename);
+            } else {
+                println!("  {}: FAILED\n{}", chapter.filename, report);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        eprintln!("Examples failed in {} chapters", failed);
+        process::exit(1);
+    }
+}
+
 fn main() {
-    use render::Format::*;
     let opt = aux::args::get();
 
-    println!("Aggregating markdown");
-    let book = prepare::create_book(
-        &opt.flag_source,
-        &opt.flag_meta,
-        RELEASE_DATE
-    ).unwrap_or_else(|e| err_exit(&e));
+    let allowlist = opt.flag_allowlist
+        .as_ref()
+        .map(|path| Allowlist::load(path).unwrap_or_else(|e| err_exit(&e)));
+
+    let backend = if opt.flag_pandoc {
+        render::Backend::Pandoc
+    } else {
+        render::Backend::Native
+    };
+
+    let config = match opt.flag_config {
+        Some(ref path) => Config::load(path).unwrap_or_else(|e| err_exit(&e)),
+        None => Config { books: vec![opt.book()], omnibuses: Vec::new(), transforms: Vec::new() },
+    };
+
+    if opt.cmd_test {
+        test_examples(&config.books, &opt);
+        return;
+    }
+
+    let mut registry = Registry::default();
+    for transform in &config.transforms {
+        let external = External { name: transform.name.clone(), command: transform.command.clone() };
+        registry.register(Box::new(external)).unwrap_or_else(|e| err_exit(&e));
+    }
+
+    let mut volumes: Vec<Volume> = config.books.iter().map(Volume::Book).collect();
+    for omnibus in &config.omnibuses {
+        let books = config.books_of(omnibus).unwrap_or_else(|e| err_exit(&e));
+        volumes.push(Volume::Omnibus(omnibus, books));
+    }
+
+    let outputs = volumes
+        .iter()
+        .map(|volume| output_for(&opt, volume))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| err_exit(&e));
+
+    let mut prepared = Vec::with_capacity(volumes.len());
+    for (volume, output) in volumes.iter().zip(&outputs) {
+        println!("Aggregating markdown for {} ({})", volume.prefix(), output.date);
+        let book = volume
+            .create(&output.date, opt.flag_number_sections, &registry)
+            .unwrap_or_else(|e| err_exit(&e));
+
+        let formats = opt.formats()
+            .or_else(|| volume.formats())
+            .unwrap_or_else(|| Format::DEFAULT.to_vec());
+        prepared.push((book, output, formats));
+    }
+
+    if opt.flag_check {
+        check_links(prepared.iter().map(|(book, output, _)| (book, output.prefix.as_str())));
+        return;
+    }
+    if opt.flag_links {
+        external_links(
+            prepared.iter().map(|(book, output, _)| (book, output.prefix.as_str())),
+            opt.flag_mirror.as_ref().map(Path::new),
+            allowlist.as_ref()
+        );
+        return;
+    }
+
+    let jobs: Vec<(&Prepared, &render::Output, Format)> = prepared
+        .iter()
+        .flat_map(|(book, output, formats)| {
+            formats.iter().map(move |format| (book, *output, *format))
+        })
+        .collect();
 
     println!("Done\n");
 
-    [Markdown, Epub, Html]
+    jobs
         .par_iter() // Because Pandoc is slow and single threaded
-        .map(|format| {
-            println!("Rendering {}...", format);
-
-            let result = render::to_file(
-                &book,
-                &opt.flag_prefix,
-                *format,
-                RELEASE_DATE,
-            );
-
-            if let Err(e) = result {
-                eprintln!("Failed to render {}:\n{}", format, e.backtrace());
-            } else {
-                println!("Finished {}", format);
+        .map(|(book, output, format)| {
+            println!("Rendering {} {}...", output.prefix, format);
+
+            let body = match output.code_width(*format) {
+                Some(width) => Cow::Owned(prepare::wrap_code(&book.markdown, width)),
+                None => Cow::Borrowed(book.markdown.as_str()),
+            };
+            match render::to_file(&book.metadata, &body, &book.contents, *format, backend, output) {
+                Ok(path) => println!("Finished {} {} at {}", output.prefix, format, path.display()),
+                Err(e) => eprintln!(
+                    "Failed to render {} {}: {}\n{}",
+                    output.prefix, format, e, e.backtrace()
+                ),
             }
         })
         .collect::<()>();
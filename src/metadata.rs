@@ -0,0 +1,332 @@
+//! The book metadata, read from the `*_meta.yml` files.
+//! Pandoc gets it back as a YAML block in front of the markdown,
+//! the native writers use the fields directly.
+
+use aux::{date, file};
+use failure::Error;
+use serde::de::{Deserialize, Deserializer};
+use serde_yaml::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Identifier {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub scheme: String,
+    pub text: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IdentifierValue {
+    Text(String),
+    Full(Identifier),
+}
+
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+fn identifiers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Identifier>, D::Error> {
+    Ok(one_or_many(deserializer)?
+        .into_iter()
+        .map(|value| match value {
+            IdentifierValue::Text(text) => Identifier { scheme: String::new(), text },
+            IdentifierValue::Full(identifier) => identifier,
+        })
+        .collect())
+}
+
+/// Pandoc's default table of contents depth
+fn default_toc_depth() -> usize { 3 }
+
+/// Documentation the books link to like `../std/index.html`, found under `doc-base`
+pub const DOC_LINKS: &[&str] = &["std", "reference", "rustc", "syntax", "book", "adv-book", "core"];
+
+fn default_doc_base() -> String { "http://doc.rust-lang.org".into() }
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct BookMetadata {
+    #[serde(default)]
+    pub title: String,
+    #[serde(rename = "author", default, deserialize_with = "one_or_many")]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub language: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(default)]
+    pub date: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub rights: String,
+    #[serde(rename = "identifier", default, deserialize_with = "identifiers", skip_serializing_if = "Vec::is_empty")]
+    pub identifiers: Vec<Identifier>,
+    #[serde(rename = "cover-image", default, skip_serializing_if = "Option::is_none")]
+    pub cover: Option<String>,
+
+    // Only used for LaTeX and PDF
+    #[serde(default = "default_toc_depth")]
+    pub toc_depth: usize,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub documentclass: String,
+    #[serde(default)]
+    pub links_as_notes: bool,
+    #[serde(default)]
+    pub verbatim_in_note: bool,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub monofont: String,
+
+    // Only used for preparing the markdown
+    /// Where `../std`-style links point, e.g. `https://doc.rust-lang.org/1.30.0`
+    #[serde(default = "default_doc_base", skip_serializing)]
+    pub doc_base: String,
+    /// More relative links, or other bases for them: `nomicon` alone is
+    /// found under `doc-base`, `nomicon: https://…` under the given URL
+    #[serde(default, skip_serializing)]
+    pub doc_links: BTreeMap<String, Option<String>>,
+
+    /// Everything else is passed on to Pandoc untouched
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+impl Default for BookMetadata {
+    fn default() -> Self {
+        BookMetadata {
+            title: String::new(),
+            authors: Vec::new(),
+            language: String::new(),
+            description: String::new(),
+            date: String::new(),
+            rights: String::new(),
+            identifiers: Vec::new(),
+            cover: None,
+            toc_depth: default_toc_depth(),
+            documentclass: String::new(),
+            links_as_notes: false,
+            verbatim_in_note: false,
+            monofont: String::new(),
+            doc_base: default_doc_base(),
+            doc_links: BTreeMap::new(),
+            other: BTreeMap::new(),
+        }
+    }
+}
+
+impl BookMetadata {
+    /// Parses a metadata file, `{release_date}` is replaced with `release_date`.
+    /// A given `release_date` also takes the place of a literal date.
+    pub fn parse(source: &str, release_date: &str) -> Result<Self, Error> {
+        let source = source.replace("{release_date}", release_date);
+        // Pandoc metadata blocks may end with `...`, which YAML documents may not
+        let source = source.trim_end().trim_end_matches("...");
+
+        let mut metadata: BookMetadata = ::serde_yaml::from_str(source)?;
+        if !release_date.is_empty() {
+            metadata.date = release_date.to_string();
+        }
+        metadata.validate()?;
+        Ok(metadata)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, release_date: &str) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let source = file::to_string(path)
+            .map_err(|e| format_err!("Failed to read metadata {}: {}", path.display(), e))?;
+
+        BookMetadata::parse(&source, release_date)
+            .map_err(|e| format_err!("Invalid metadata {}: {}", path.display(), e))
+    }
+
+    /// The metadata of an omnibus volume: authors and rights of all books,
+    /// everything else but the descriptive fields from the first book
+    pub fn combine(title: &str, books: &[BookMetadata]) -> Result<Self, Error> {
+        let first = books.first().ok_or_else(|| format_err!("No books to combine"))?;
+
+        let mut authors: Vec<String> = Vec::new();
+        let mut rights: Vec<&str> = Vec::new();
+        for book in books {
+            for author in &book.authors {
+                if !authors.contains(author) { authors.push(author.clone()) }
+            }
+            if !book.rights.is_empty() && !rights.contains(&book.rights.as_str()) {
+                rights.push(&book.rights);
+            }
+        }
+
+        let combined = BookMetadata {
+            title: title.to_string(),
+            authors,
+            description: String::new(),
+            rights: rights.join("; "),
+            identifiers: Vec::new(),
+            cover: None,
+            ..first.clone()
+        };
+        combined.validate()?;
+        Ok(combined)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let missing: Vec<&str> = [
+            ("title", self.title.is_empty()),
+            ("author", self.authors.is_empty()),
+            ("language", self.language.is_empty()),
+            ("date", self.date.is_empty()),
+        ].iter()
+            .filter(|&&(_, is_missing)| is_missing)
+            .map(|&(field, _)| field)
+            .collect();
+
+        if !missing.is_empty() {
+            bail!("missing required fields: {}", missing.join(", "));
+        }
+
+        date::parse(&self.date)?;
+        Ok(())
+    }
+
+    /// The URL of every `../{name}` a chapter may link to
+    pub fn doc_urls(&self) -> HashMap<String, String> {
+        let base = self.doc_base.trim_end_matches('/');
+        let mut urls: HashMap<String, String> = DOC_LINKS
+            .iter()
+            .map(|name| (name.to_string(), format!("{}/{}", base, name)))
+            .collect();
+
+        for (name, url) in &self.doc_links {
+            let url = match url {
+                Some(url) => url.trim_end_matches('/').to_string(),
+                None => format!("{}/{}", base, name),
+            };
+            urls.insert(name.clone(), url);
+        }
+
+        urls
+    }
+
+    /// All authors as one line, for formats without author lists
+    pub fn author(&self) -> String {
+        self.authors.join(", ")
+    }
+
+    /// The metadata as a Pandoc YAML metadata block
+    pub fn to_yaml(&self) -> Result<String, Error> {
+        Ok(::serde_yaml::to_string(self)? + "\n...\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const META: &str = indoc!(r#"
+        ---
+        title: "The Rustonomicon"
+        author: "The Rust Team"
+        date: {release_date}
+        language: en
+        rights: "MIT or Apache-2.0"
+        identifier:
+          - scheme: URL
+            text: "https://doc.rust-lang.org/nomicon/"
+        documentclass: book
+        links-as-notes: true
+        toc-depth: 2
+        monofont: "DejaVu Sans Mono"
+        ...
+        "#);
+
+    #[test]
+    fn parse_metadata() {
+        let metadata = BookMetadata::parse(META, "2016-10-01").unwrap();
+
+        assert_eq!(
+            BookMetadata {
+                title: "The Rustonomicon".into(),
+                authors: vec!["The Rust Team".into()],
+                language: "en".into(),
+                date: "2016-10-01".into(),
+                rights: "MIT or Apache-2.0".into(),
+                identifiers: vec![Identifier {
+                    scheme: "URL".into(),
+                    text: "https://doc.rust-lang.org/nomicon/".into(),
+                }],
+                toc_depth: 2,
+                documentclass: "book".into(),
+                links_as_notes: true,
+                monofont: "DejaVu Sans Mono".into(),
+                ..BookMetadata::default()
+            },
+            metadata
+        );
+
+        let yaml = metadata.to_yaml().unwrap();
+        assert_eq!(metadata, BookMetadata::parse(&yaml, "").unwrap());
+        assert_eq!("2018-05-10", BookMetadata::parse(&yaml, "2018-05-10").unwrap().date);
+    }
+
+    #[test]
+    fn combine_metadata() {
+        let nomicon = BookMetadata::parse(META, "2016-10-01").unwrap();
+        let trpl = BookMetadata {
+            title: "The Rust Programming Language".into(),
+            authors: vec!["Steve Klabnik".into(), "The Rust Team".into()],
+            ..nomicon.clone()
+        };
+
+        let combined = BookMetadata::combine("The Rust Library", &[trpl, nomicon]).unwrap();
+        assert_eq!("The Rust Library", combined.title);
+        assert_eq!("Steve Klabnik, The Rust Team", combined.author());
+        assert_eq!("MIT or Apache-2.0", combined.rights);
+        assert_eq!("2016-10-01", combined.date);
+        assert!(combined.identifiers.is_empty());
+
+        assert!(BookMetadata::combine("Nothing", &[]).is_err());
+    }
+
+    #[test]
+    fn doc_urls() {
+        let urls = BookMetadata::default().doc_urls();
+        assert_eq!("http://doc.rust-lang.org/std", urls["std"]);
+        assert_eq!(DOC_LINKS.len(), urls.len());
+
+        let metadata = BookMetadata::parse(&format!("{}{}", META.trim_end().trim_end_matches("..."), indoc!(r#"
+            doc-base: "https://doc.rust-lang.org/1.30.0/"
+            doc-links:
+              nomicon:
+              unstable-book: "https://doc.rust-lang.org/nightly/unstable-book"
+            "#)), "2016-10-01").unwrap();
+        let urls = metadata.doc_urls();
+        assert_eq!("https://doc.rust-lang.org/1.30.0/std", urls["std"]);
+        assert_eq!("https://doc.rust-lang.org/1.30.0/nomicon", urls["nomicon"]);
+        assert_eq!("https://doc.rust-lang.org/nightly/unstable-book", urls["unstable-book"]);
+        assert!(!metadata.to_yaml().unwrap().contains("doc-base"));
+    }
+
+    #[test]
+    fn invalid_metadata() {
+        let error = BookMetadata::parse("---\ntitle: x\ndate: 2016-10-01\n", "").unwrap_err();
+        assert_eq!("missing required fields: author, language", error.to_string());
+
+        assert!(BookMetadata::parse(META, "2016-10-32").is_err());
+        assert!(BookMetadata::parse("---\ntitle: [x]\n", "").is_err());
+    }
+}
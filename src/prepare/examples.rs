@@ -0,0 +1,254 @@
+//! The Rust examples of a book, tested the way rustdoc tests documentation:
+//! each chapter's Rust code blocks, attributes and hidden lines included,
+//! go into a markdown file of their own at the lines they were written at,
+//! which `rustdoc --test` then compiles and runs.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use failure::{Error, ResultExt};
+use ::aux::file;
+use super::{
+    markdown::{fence_parts, info_attributes, Document, Node},
+    toc,
+};
+
+/// Code block attributes rustdoc understands, any others make a block no Rust code
+const RUSTDOC_ATTRIBUTES: &[&str] = &[
+    "rust", "ignore", "no_run", "should_panic", "compile_fail", "test_harness", "allow_fail",
+];
+
+/// Whether rustdoc tests a code block with the `info` string,
+/// which may look like `rust,ignore` or `{rust,no_run}`
+fn is_rust(info: &str) -> bool {
+    info_attributes(info).all(|attribute| {
+        RUSTDOC_ATTRIBUTES.contains(&attribute)
+            || attribute.starts_with("ignore-")
+            || attribute.starts_with("edition")
+    })
+}
+
+/// `block` without the indentation all its lines share, beyond `keep` spaces.
+/// Examples in lists are indented, and rustdoc reads fences indented by
+/// four or more spaces as indented code.
+fn dedent(block: &str, keep: usize) -> String {
+    let indent = |line: &str| line.len() - line.trim_start_matches(' ').len();
+    let common = block
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(indent)
+        .min()
+        .unwrap_or(0)
+        .saturating_sub(keep);
+
+    block
+        .lines()
+        .map(|line| format!("{}\n", &line[indent(line).min(common)..]))
+        .collect()
+}
+
+/// The Rust code blocks of one chapter file
+#[derive(Debug)]
+pub struct ChapterExamples {
+    pub filename: String,
+    /// Code blocks with the line they start at, info strings the way rustdoc reads them
+    pub blocks: Vec<(usize, String)>,
+}
+
+impl ChapterExamples {
+    pub fn extract(filename: &str, markdown: &str) -> Self {
+        let document = Document::parse(markdown);
+        let blocks = document
+            .numbered_nodes()
+            .filter_map(|(line, node)| match node {
+                Node::CodeBlock { fence, code, closing } => match fence_parts(fence) {
+                    // Indented code, which needs its four spaces
+                    None => Some((line, dedent(&node.to_string(), 4))),
+                    // rustdoc does not read `{rust,ignore}` or `{.rust .ignore}`
+                    Some((indent, marker, info)) if is_rust(info) => {
+                        let attributes: Vec<&str> = info_attributes(info).collect();
+                        let block = Node::CodeBlock {
+                            fence: format!("{}{}{}", indent, marker, attributes.join(",")).into(),
+                            code: code.clone(),
+                            closing: closing.clone(),
+                        };
+                        Some((line, dedent(&block.to_string(), 0)))
+                    }
+                    Some(_) => None,
+                },
+                _ => None,
+            })
+            .collect();
+
+        ChapterExamples { filename: filename.to_string(), blocks }
+    }
+
+    /// Markdown with nothing but the code blocks, each at its line in the chapter,
+    /// so rustdoc reports failures at the chapter's own lines
+    pub fn harness(&self) -> String {
+        let mut harness = String::new();
+        let mut line = 1;
+
+        for (start, block) in &self.blocks {
+            while line < *start {
+                harness.push('\n');
+                line += 1;
+            }
+            harness.push_str(block);
+            if !block.ends_with('\n') { harness.push('\n') }
+            line += block.trim_end_matches('\n').matches('\n').count() + 1;
+        }
+
+        harness
+    }
+}
+
+/// The examples of the introduction and every chapter of the book in `path_prefix`
+pub fn extract_book(path_prefix: &Path) -> Result<Vec<ChapterExamples>, Error> {
+    let toc = toc::TableOfContents::load(path_prefix)?;
+    let tree = toc::ChapterTree::new(&toc);
+    // Unless `SUMMARY.md` lists it as a chapter
+    let introduction = match tree.find(toc::INTRODUCTION) {
+        Some(_) => None,
+        None => Some(toc::INTRODUCTION.to_string()),
+    };
+    let filenames = introduction
+        .into_iter()
+        .chain(tree.iter().filter_map(|node| node.chapter.filename.clone()));
+
+    let mut chapters = Vec::new();
+    for filename in filenames {
+        let path = path_prefix.join(&filename);
+        let markdown = file::to_string(&path)
+            .map_err(|e| format_err!("Failed to read {}: {}", path.display(), e))?;
+        chapters.push(ChapterExamples::extract(&filename, &markdown));
+    }
+
+    Ok(chapters)
+}
+
+/// Writes the harness of `chapter` below `directory`, at the chapter's path
+pub fn write_harness(chapter: &ChapterExamples, directory: &Path) -> Result<PathBuf, Error> {
+    let path = directory.join(&chapter.filename);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format_err!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    fs::write(&path, chapter.harness())
+        .map_err(|e| format_err!("Failed to write {}: {}", path.display(), e))?;
+
+    Ok(path)
+}
+
+/// Runs `rustdoc --test` on a harness, with rustup's `+toolchain` if given,
+/// returning whether all examples passed and rustdoc's report
+pub fn run_rustdoc(harness: &Path, toolchain: Option<&str>, edition: Option<&str>) -> Result<(bool, String), Error> {
+    let mut rustdoc = Command::new("rustdoc");
+    if let Some(toolchain) = toolchain {
+        rustdoc.arg(format!("+{}", toolchain));
+    }
+    if let Some(edition) = edition {
+        rustdoc.arg(format!("--edition={}", edition));
+    }
+
+    let output = rustdoc
+        .arg("--test")
+        .arg(harness)
+        .output()
+        .context("Failed to execute rustdoc")?;
+
+    let report = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    Ok((output.status.success(), report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAPTER: &str = indoc!("
+        # Ownership
+
+        ```rust
+        # fn main() {
+        let s = String::from(\"hello\");
+        # }
+        ```
+
+        ```text
+        error[E0382]: use of moved value
+        ```
+
+        Some prose.
+
+        ```{rust,ignore}
+        let x = y;
+        ```
+
+        ```
+        fn untagged() {}
+        ```
+
+        1. In a list:
+
+            ```rust
+            let listed = 1;
+
+            assert_eq!(listed, 1);
+            ```
+
+                   let indented = 2;
+        ");
+
+    #[test]
+    fn rust_blocks() {
+        assert!(is_rust(""));
+        assert!(is_rust("rust,should_panic"));
+        assert!(is_rust("{rust,no_run}"));
+        assert!(is_rust("ignore-windows"));
+        assert!(!is_rust("text"));
+        assert!(!is_rust("rust,text"));
+
+        let chapter = ChapterExamples::extract("ch04/ownership.md", CHAPTER);
+        let lines: Vec<usize> = chapter.blocks.iter().map(|(line, _)| *line).collect();
+        assert_eq!(vec![3, 15, 19, 25, 31], lines);
+
+        assert_eq!(
+            indoc!("
+
+
+                ```rust
+                # fn main() {
+                let s = String::from(\"hello\");
+                # }
+                ```
+
+
+
+
+
+
+
+                ```rust,ignore
+                let x = y;
+                ```
+
+                ```
+                fn untagged() {}
+                ```
+
+
+
+                ```rust
+                let listed = 1;
+
+                assert_eq!(listed, 1);
+                ```
+
+                    let indented = 2;
+                "),
+            chapter.harness()
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Links of the aggregated book, remembered with the place they were
+//! written at so broken ones can be reported: internal links to anchors,
+//! and external links checked against a mirror or an allowlist instead
+//! of the network.
+
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+    path::{Path, PathBuf},
+};
+use failure::Error;
+use ::aux::file;
+use super::markdown::Document;
+
+mod reg {
+    pub const SCHEME: &str = r"^[A-Za-z][A-Za-z0-9+.-]*:";
+    pub const URL: &str = r"^https?://(?P<host>[^/?#]+)(?P<path>[^?#]*)";
+}
+
+/// A link to an anchor inside the book or to an external URL
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub file: PathBuf,
+    pub line: usize,
+    /// `#anchor` or a URL with a scheme, like `https://…` or `ftp://…`
+    pub target: String,
+}
+
+impl Link {
+    pub fn is_internal(&self) -> bool { self.target.starts_with('#') }
+}
+
+impl Display for Link {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{}:{}: {}", self.file.display(), self.line, self.target)
+    }
+}
+
+/// The links of a prepared chapter, at the lines its nodes came from
+pub fn chapter_links(chapter: &Document, file: PathBuf) -> Vec<Link> {
+    chapter
+        .destinations()
+        .into_iter()
+        .filter(|(_, target)| target.starts_with('#') || is_url(target))
+        .map(|(line, target)| Link { file: file.clone(), line, target: target.to_string() })
+        .collect()
+}
+
+/// Whether `target` starts with a scheme, rather than being a path in the book
+fn is_url(target: &str) -> bool {
+    lazy_static_regex!(SCHEME, reg::SCHEME);
+    SCHEME.is_match(target)
+}
+
+/// Internal links to none of the `anchors`
+pub fn dangling<'a>(links: &'a [Link], anchors: &HashSet<String>) -> Vec<&'a Link> {
+    links
+        .iter()
+        .filter(|link| link.is_internal() && !anchors.contains(&link.target[1..]))
+        .collect()
+}
+
+/// URLs known to be fine, one per line. A trailing `*` allows every URL
+/// starting with the rest, `#` starts a comment.
+#[derive(Debug, Default)]
+pub struct Allowlist {
+    urls: HashSet<String>,
+    prefixes: Vec<String>,
+}
+
+impl Allowlist {
+    pub fn parse(source: &str) -> Self {
+        let mut allowlist = Allowlist::default();
+
+        for line in source.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') { continue }
+
+            if line.ends_with('*') {
+                allowlist.prefixes.push(line.trim_end_matches('*').to_string());
+            } else {
+                allowlist.urls.insert(without_fragment(line).to_string());
+            }
+        }
+
+        allowlist
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        file::to_string(path)
+            .map(|source| Allowlist::parse(&source))
+            .map_err(|e| format_err!("Failed to read allowlist {}: {}", path.display(), e))
+    }
+
+    pub fn allows(&self, url: &str) -> bool {
+        self.urls.contains(without_fragment(url))
+            || self.prefixes.iter().any(|prefix| url.starts_with(prefix.as_str()))
+    }
+}
+
+fn without_fragment(url: &str) -> &str {
+    url.split('#').next().unwrap_or(url)
+}
+
+/// Whether `url` is an HTTP(S) URL, which a mirror can have a copy of
+pub fn is_mirrorable(url: &str) -> bool {
+    lazy_static_regex!(URL, reg::URL);
+    URL.is_match(url)
+}
+
+/// Where a mirror made with `wget --mirror` keeps `url`: `{host}/{path}`,
+/// `index.html` for directories
+pub fn mirror_path(mirror: &Path, url: &str) -> Option<PathBuf> {
+    lazy_static_regex!(URL, reg::URL);
+
+    let parts = URL.captures(url)?;
+    let path = parts["path"].trim_start_matches('/');
+    let mut local = mirror.join(&parts["host"]).join(path);
+    if path.is_empty() || path.ends_with('/') || local.is_dir() {
+        local.push("index.html");
+    }
+    Some(local)
+}
+
+/// External links that are neither in the `mirror` nor on the `allowlist`,
+/// including all links that can not be mirrored and are not allowed
+pub fn unverified<'a>(links: &'a [Link], mirror: Option<&Path>, allowlist: &Allowlist) -> Vec<&'a Link> {
+    links
+        .iter()
+        .filter(|link| !link.is_internal())
+        .filter(|link| !allowlist.allows(&link.target))
+        .filter(|link| match mirror.and_then(|mirror| mirror_path(mirror, &link.target)) {
+            Some(local) => !local.is_file(),
+            None => true,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAPTER: &str = indoc!("
+        # Errors
+
+        See [unwrap](#unwrapping), [the result](#sec--result)
+        and [somewhere](https://doc.rust-lang.org/#nowhere).
+
+        ```rust
+        let x = a[i](#not-a-link);
+        ```
+
+        ~~~
+        [tilde](#not-a-link)
+        ~~~
+
+        [try]: #the-try-macro
+        [rfc]: <https://github.com/rust-lang/rfcs/pull/243>
+        [paper]: ftp://ftp.cs.washington.edu/tr/2015/03/UW-CSE-15-03-02.pdf
+        [guide](ch01.md)
+
+        ## Handling [x](#missing)
+        ");
+
+    #[test]
+    fn links_in_chapter() {
+        let links = chapter_links(&Document::parse(CHAPTER), PathBuf::from("trpl/errors.md"));
+        let found: Vec<String> = links.iter().map(ToString::to_string).collect();
+
+        assert_eq!(
+            vec![
+                "trpl/errors.md:3: #unwrapping",
+                "trpl/errors.md:3: #sec--result",
+                "trpl/errors.md:4: https://doc.rust-lang.org/#nowhere",
+                "trpl/errors.md:14: #the-try-macro",
+                "trpl/errors.md:15: https://github.com/rust-lang/rfcs/pull/243",
+                "trpl/errors.md:16: ftp://ftp.cs.washington.edu/tr/2015/03/UW-CSE-15-03-02.pdf",
+                "trpl/errors.md:19: #missing",
+            ],
+            found
+        );
+
+        let anchors = ["unwrapping", "sec--result"].iter().map(ToString::to_string).collect();
+        assert_eq!(vec![&links[3], &links[6]], dangling(&links, &anchors));
+    }
+
+    #[test]
+    fn external_links() {
+        let allowlist = Allowlist::parse(indoc!("
+            # Checked by hand
+            https://github.com/rust-lang/rfcs/*
+            http://www.example.com/page.html
+            "));
+        assert!(allowlist.allows("https://github.com/rust-lang/rfcs/pull/243"));
+        assert!(allowlist.allows("http://www.example.com/page.html#top"));
+        assert!(!allowlist.allows("http://www.example.com/"));
+
+        let mirror = Path::new("mirror");
+        assert_eq!(
+            Some(PathBuf::from("mirror/doc.rust-lang.org/std/io/index.html")),
+            mirror_path(mirror, "http://doc.rust-lang.org/std/io/index.html#errors")
+        );
+        assert_eq!(
+            Some(PathBuf::from("mirror/doc.rust-lang.org/index.html")),
+            mirror_path(mirror, "https://doc.rust-lang.org/")
+        );
+        assert_eq!(None, mirror_path(mirror, "ftp://example.com/file"));
+        assert!(!is_mirrorable("irc://irc.mozilla.org/#rust"));
+
+        let link = |target: &str| Link { file: PathBuf::from("a.md"), line: 1, target: target.into() };
+        let links = vec![
+            link("#anchor"),
+            link("https://github.com/rust-lang/rfcs/pull/243"),
+            link("http://doc.rust-lang.org/std/io/index.html"),
+            link("ftp://ftp.cs.washington.edu/tr/2015/03/UW-CSE-15-03-02.pdf"),
+        ];
+        assert_eq!(vec![&links[2], &links[3]], unverified(&links, Some(mirror), &allowlist));
+    }
+}
@@ -0,0 +1,528 @@
+//! A chapter's markdown cut into the nodes its preparation treats apart,
+//! as the CommonMark parser sees them: fences of tildes or more backticks,
+//! indented code and HTML blocks are never mistaken for prose or headings.
+//! Written back out, nodes nobody changed keep their source text.
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use std::{
+    borrow::Cow,
+    fmt::{self, Display, Formatter},
+};
+
+mod reg {
+    /// Link definitions leave no trace in the parser's events, footnote definitions do
+    pub const DEFINITION: &str = r"(?m)^ {0,3}\[(?P<label>[^\]^][^\]]*)\]:[ \t]*(?P<destination><[^>\s]*>|\S+)";
+}
+
+/// A part of a chapter's markdown
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node<'a> {
+    /// A heading outside of lists and block quotes, ATX or setext,
+    /// written back in ATX form
+    Heading { level: usize, title: Cow<'a, str> },
+    /// `fence` is the opening line, empty for indented code,
+    /// `closing` the closing line if the block has one
+    CodeBlock { fence: Cow<'a, str>, code: Cow<'a, str>, closing: Cow<'a, str> },
+    Html(Cow<'a, str>),
+    /// A code span with its backticks
+    InlineCode(Cow<'a, str>),
+    /// Where an inline link, an autolink, an image or a link definition
+    /// points to, without angle brackets or title
+    Destination(Cow<'a, str>),
+    /// The label of a reference link `[text][label]`, or of a link
+    /// definition `[label]: destination` if `definition` is set
+    Label { name: Cow<'a, str>, definition: bool },
+    /// The name of a footnote reference `[^name]`, or of the footnote
+    /// definition `[^name]: text` if `definition` is set
+    Footnote { name: Cow<'a, str>, definition: bool },
+    /// Everything else: paragraphs, lists and the brackets around the nodes above
+    Text(Cow<'a, str>),
+}
+
+impl<'a> Node<'a> {
+    pub fn into_owned(self) -> Node<'static> {
+        fn owned(text: Cow<str>) -> Cow<'static, str> { Cow::Owned(text.into_owned()) }
+
+        match self {
+            Node::Heading { level, title } => Node::Heading { level, title: owned(title) },
+            Node::CodeBlock { fence, code, closing } => Node::CodeBlock {
+                fence: owned(fence),
+                code: owned(code),
+                closing: owned(closing),
+            },
+            Node::Html(html) => Node::Html(owned(html)),
+            Node::InlineCode(code) => Node::InlineCode(owned(code)),
+            Node::Destination(destination) => Node::Destination(owned(destination)),
+            Node::Label { name, definition } => Node::Label { name: owned(name), definition },
+            Node::Footnote { name, definition } => Node::Footnote { name: owned(name), definition },
+            Node::Text(text) => Node::Text(owned(text)),
+        }
+    }
+}
+
+impl<'a> Display for Node<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            // Pandoc wants a blank line after a heading
+            Node::Heading { level, title } => write!(fmt, "{} {}\n\n", "#".repeat(*level), title),
+            Node::CodeBlock { fence, code, closing } if fence.is_empty() => write!(fmt, "{}{}", code, closing),
+            Node::CodeBlock { fence, code, closing } => write!(fmt, "{}\n{}{}", fence, code, closing),
+            Node::Html(html) => fmt.write_str(html),
+            Node::InlineCode(code) => fmt.write_str(code),
+            Node::Destination(destination) => fmt.write_str(destination),
+            Node::Label { name, .. } | Node::Footnote { name, .. } => fmt.write_str(name),
+            Node::Text(text) => fmt.write_str(text),
+        }
+    }
+}
+
+/// The parts of a fence line: indentation, the backticks or tildes, info string
+pub fn fence_parts(line: &str) -> Option<(&str, &str, &str)> {
+    let rest = line.trim_start_matches(&[' ', '>'][..]);
+    let marker = rest.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let length = rest.len() - rest.trim_start_matches(marker).len();
+    if length < 3 { return None }
+
+    let indent = line.len() - rest.len();
+    Some((&line[..indent], &rest[..length], rest[length..].trim()))
+}
+
+/// The words of an info string like `rust,ignore`, `{rust,no_run}` or `{.rust .ignore}`
+pub fn info_attributes(info: &str) -> impl Iterator<Item = &str> {
+    info
+        .split(|c: char| c == ',' || c == '{' || c == '}' || c.is_whitespace())
+        .map(|attribute| attribute.trim_start_matches('.'))
+        .filter(|attribute| !attribute.is_empty())
+}
+
+/// The text of a hidden line of a Rust example, a `#` alone or followed
+/// by whitespace, `None` for other lines
+pub fn hidden_line(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    if !trimmed.starts_with('#') { return None }
+
+    let rest = &trimmed[1..];
+    match rest.chars().next() {
+        None => Some(""),
+        Some(space) if space.is_whitespace() => Some(&rest[space.len_utf8()..]),
+        Some(_) => None,
+    }
+}
+
+fn is_closing_fence(line: &str, opening: &str) -> bool {
+    match fence_parts(line.trim_end()) {
+        Some((_, marker, info)) => info.is_empty() && marker.starts_with(opening),
+        None => false,
+    }
+}
+
+fn line_start(markdown: &str, offset: usize) -> usize {
+    markdown[..offset].rfind('\n').map_or(0, |newline| newline + 1)
+}
+
+/// The heading in `markdown[start..end]`, from its line start past its newline
+fn heading(markdown: &str, start: usize, end: usize, level: i32) -> Node<'_> {
+    let source = markdown[start..end].trim();
+
+    let title = if source.starts_with('#') {
+        let title = source.trim_start_matches('#').trim();
+        // An optional closing sequence of `#`s
+        let unclosed = title.trim_end_matches('#');
+        Cow::Borrowed(if unclosed.is_empty() || unclosed.ends_with(' ') { unclosed.trim_end() } else { title })
+    } else {
+        // The last line underlines the others
+        let lines: Vec<&str> = source.lines().map(str::trim).collect();
+        Cow::Owned(lines[..lines.len() - 1].join(" "))
+    };
+
+    Node::Heading { level: level as usize, title }
+}
+
+/// The destination or label of the link or image whose text goes from
+/// `start` to `text_end` and which ends at `end`
+fn link_target(markdown: &str, start: usize, text_end: usize, end: usize) -> Option<(usize, usize, Node<'_>)> {
+    let tail = &markdown[text_end..end];
+
+    if tail.starts_with("](") && tail.ends_with(')') {
+        let inner = &tail[2..tail.len() - 1];
+        let trimmed = inner.trim_start();
+        let (bracket, destination) = if trimmed.starts_with('<') {
+            (1, &trimmed[1..trimmed.find('>')?])
+        } else {
+            (0, trimmed.split_whitespace().next()?)
+        };
+        let destination_start = text_end + 2 + inner.len() - trimmed.len() + bracket;
+        Some((destination_start, destination_start + destination.len(), Node::Destination(destination.into())))
+    } else if tail.starts_with("][") && tail.len() > 3 && tail.ends_with(']') {
+        Some((text_end + 2, end - 1, Node::Label { name: tail[2..tail.len() - 1].into(), definition: false }))
+    } else if tail == ">" && markdown[..start].ends_with('<') {
+        // An autolink, its text is its destination
+        Some((start, text_end, Node::Destination(markdown[start..text_end].into())))
+    } else {
+        None
+    }
+}
+
+/// The footnote name in `markdown[start..end]`, between `[^` and `]`
+fn footnote(markdown: &str, start: usize, end: usize, definition: bool) -> Option<(usize, usize, Node<'_>)> {
+    let source = &markdown[start..end];
+    let name_start = start + source.rfind("[^")? + 2;
+    let name_end = start + source.rfind(']')?;
+    if name_end < name_start { return None }
+
+    Some((name_start, name_end, Node::Footnote { name: markdown[name_start..name_end].into(), definition }))
+}
+
+/// The code block whose content starts at `content` and which ends at `end`,
+/// starting with its opening line if it has one
+fn code_block(markdown: &str, content: usize, end: usize) -> (usize, Node<'_>) {
+    let start = line_start(markdown, content.saturating_sub(1));
+    let opening = markdown[start..content].trim_end_matches('\n');
+
+    match fence_parts(opening) {
+        Some((_, marker, _)) => {
+            let body = &markdown[content..end];
+            let last_line = line_start(body, body.trim_end_matches('\n').len());
+            let (code, closing) = if is_closing_fence(&body[last_line..], marker) {
+                body.split_at(last_line)
+            } else {
+                (body, "")
+            };
+            (start, Node::CodeBlock { fence: opening.into(), code: code.into(), closing: closing.into() })
+        },
+        None => (start, Node::CodeBlock { fence: "".into(), code: markdown[start..end].into(), closing: "".into() }),
+    }
+}
+
+/// The nodes of a chapter, each with the source line it starts at
+#[derive(Debug)]
+pub struct Document<'a> {
+    nodes: Vec<(usize, Node<'a>)>,
+}
+
+impl<'a> Document<'a> {
+    pub fn parse(markdown: &'a str) -> Self {
+        lazy_static_regex!(DEFINITION, reg::DEFINITION);
+
+        let mut parser = Parser::new_ext(markdown, Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES);
+        // Nodes other than text with their source range
+        let mut spans: Vec<(usize, usize, Node)> = Vec::new();
+        // Source of text, code and HTML, where link definitions can not be
+        let mut covered: Vec<(usize, usize)> = Vec::new();
+        let mut containers = 0;
+        // Offsets are where the last event ended
+        let mut previous = 0;
+        // Where the current heading, code block and code span start, and the text of open links
+        let (mut heading_start, mut block_start, mut code_start) = (0, 0, 0);
+        let mut link_starts = Vec::new();
+
+        while let Some(event) = parser.next() {
+            let offset = parser.get_offset();
+
+            match event {
+                Event::Start(Tag::BlockQuote) | Event::Start(Tag::List(_)) => containers += 1,
+                Event::Start(Tag::FootnoteDefinition(_)) => {
+                    containers += 1;
+                    spans.extend(footnote(markdown, previous, offset, true));
+                },
+                Event::End(Tag::BlockQuote) | Event::End(Tag::List(_)) | Event::End(Tag::FootnoteDefinition(_)) => {
+                    containers -= 1;
+                },
+                Event::Start(Tag::Header(_)) => heading_start = line_start(markdown, offset),
+                Event::End(Tag::Header(level)) if containers == 0 => {
+                    // Its code spans are part of the title
+                    while spans.last().map_or(false, |span| span.0 >= heading_start) {
+                        spans.pop();
+                    }
+                    spans.push((heading_start, offset, heading(markdown, heading_start, offset, level)));
+                },
+                Event::Start(Tag::CodeBlock(_)) => block_start = offset,
+                Event::End(Tag::CodeBlock(_)) => {
+                    let (start, block) = code_block(markdown, block_start, offset);
+                    spans.push((start, offset, block));
+                },
+                // The start offset is past the backticks, the previous event ends before them
+                Event::Start(Tag::Code) => {
+                    code_start = markdown[previous..offset].find('`').map_or(offset, |tick| previous + tick);
+                },
+                Event::End(Tag::Code) if markdown[code_start..].starts_with('`') => {
+                    spans.push((code_start, offset, Node::InlineCode(markdown[code_start..offset].into())));
+                },
+                Event::Start(Tag::Link(..)) | Event::Start(Tag::Image(..)) => link_starts.push(offset),
+                Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => {
+                    let start = link_starts.pop().unwrap_or(previous);
+                    spans.extend(link_target(markdown, start, previous, offset));
+                },
+                Event::FootnoteReference(_) => spans.extend(footnote(markdown, previous, offset, false)),
+                Event::Html(ref html) if containers == 0 && markdown[..offset].ends_with(&**html) => {
+                    let start = offset - html.len();
+                    spans.push((start, offset, Node::Html(markdown[start..offset].into())));
+                    covered.push((start, offset));
+                },
+                Event::Text(_) | Event::Html(_) | Event::InlineHtml(_) => covered.push((previous, offset)),
+                _ => {},
+            }
+
+            previous = offset;
+        }
+
+        for definition in DEFINITION.captures_iter(markdown) {
+            let label = definition.name("label").unwrap();
+            if covered.iter().any(|&(start, end)| start <= label.start() && label.start() < end) { continue }
+
+            let destination = definition.name("destination").unwrap();
+            let (start, end) = if destination.as_str().starts_with('<') {
+                (destination.start() + 1, destination.end() - 1)
+            } else {
+                (destination.start(), destination.end())
+            };
+            spans.push((label.start(), label.end(), Node::Label { name: label.as_str().into(), definition: true }));
+            spans.push((start, end, Node::Destination(markdown[start..end].into())));
+        }
+        spans.sort_by_key(|span| span.0);
+
+        let mut nodes = Vec::with_capacity(2 * spans.len() + 1);
+        let mut line = 1;
+        let mut position = 0;
+        let mut push = |nodes: &mut Vec<(usize, Node<'a>)>, source: &str, node: Node<'a>| {
+            nodes.push((line, node));
+            line += source.matches('\n').count();
+        };
+
+        for (start, end, node) in spans {
+            if start < position { continue }
+            if start > position {
+                let text = &markdown[position..start];
+                push(&mut nodes, text, Node::Text(Cow::Borrowed(text)));
+            }
+            push(&mut nodes, &markdown[start..end], node);
+            position = end;
+        }
+        if position < markdown.len() {
+            let text = &markdown[position..];
+            push(&mut nodes, text, Node::Text(Cow::Borrowed(text)));
+        }
+
+        Document { nodes }
+    }
+
+    /// Keeps the nodes when the markdown they were parsed from goes away
+    pub fn into_owned(self) -> Document<'static> {
+        Document { nodes: self.nodes.into_iter().map(|(line, node)| (line, node.into_owned())).collect() }
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node<'a>> {
+        self.nodes.iter().map(|(_, node)| node)
+    }
+
+    pub fn nodes_mut(&mut self) -> impl Iterator<Item = &mut Node<'a>> {
+        self.nodes.iter_mut().map(|(_, node)| node)
+    }
+
+    /// The nodes with the source line they start at
+    pub fn numbered_nodes(&self) -> impl Iterator<Item = (usize, &Node<'a>)> {
+        self.nodes.iter().map(|(line, node)| (*line, node))
+    }
+
+    /// Rewrites the prose and heading titles, leaving code and HTML alone
+    pub fn map_prose<F>(&mut self, transform: F)
+    where
+        F: Fn(&str) -> String,
+    {
+        for node in self.nodes_mut() {
+            match node {
+                Node::Text(text) => *text = Cow::Owned(transform(text)),
+                Node::Heading { title, .. } => *title = Cow::Owned(transform(title)),
+                _ => {},
+            }
+        }
+    }
+
+    /// Rewrites where links, images and link definitions point to,
+    /// in prose and heading titles alike
+    pub fn map_destinations<F>(&mut self, transform: F)
+    where
+        F: Fn(&str) -> String,
+    {
+        for node in self.nodes_mut() {
+            match node {
+                Node::Destination(destination) => *destination = Cow::Owned(transform(destination)),
+                Node::Heading { title, .. } => {
+                    let mut spans = Document::parse(title);
+                    for node in spans.nodes_mut() {
+                        if let Node::Destination(destination) = node {
+                            *destination = Cow::Owned(transform(destination));
+                        }
+                    }
+
+                    let mapped = spans.to_string();
+                    if mapped != *title { *title = Cow::Owned(mapped) }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Where the links, images and link definitions point to, with their
+    /// source line, those in heading titles included
+    pub fn destinations(&self) -> Vec<(usize, &str)> {
+        let mut destinations = Vec::new();
+
+        for (line, node) in self.numbered_nodes() {
+            match node {
+                Node::Destination(destination) => destinations.push((line, &**destination)),
+                Node::Heading { title, .. } => destinations.extend(
+                    Document::parse(title)
+                        .nodes()
+                        .filter_map(|node| match node {
+                            Node::Destination(Cow::Borrowed(destination)) => Some((line, *destination)),
+                            _ => None,
+                        })
+                ),
+                _ => {},
+            }
+        }
+        destinations
+    }
+}
+
+impl<'a> Display for Document<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        for node in self.nodes() {
+            write!(fmt, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAPTER: &str = indoc!("
+        Setext
+        ======
+
+        Some `a[i](#x)` and ``` `` ``` [links](#y).
+
+        ~~~rust
+        # hidden
+        ```
+        ~~~
+
+        ````
+        ```
+        ## Not a heading
+        ```
+        ````
+
+            indented
+            # code
+
+        <div>
+        # Not a heading either
+        </div>
+
+        > # Quoted
+
+        ## Closed `code` ##
+        ");
+
+    #[test]
+    fn chapter_nodes() {
+        let document = Document::parse(CHAPTER);
+        let nodes: Vec<&Node> = document.nodes().filter(|node| match node { Node::Text(_) => false, _ => true }).collect();
+
+        assert_eq!(
+            vec![
+                &Node::Heading { level: 1, title: "Setext".into() },
+                &Node::InlineCode("`a[i](#x)`".into()),
+                &Node::InlineCode("``` `` ```".into()),
+                &Node::Destination("#y".into()),
+                &Node::CodeBlock { fence: "~~~rust".into(), code: "# hidden\n```\n".into(), closing: "~~~\n".into() },
+                &Node::CodeBlock { fence: "````".into(), code: "```\n## Not a heading\n```\n".into(), closing: "````\n".into() },
+                &Node::CodeBlock { fence: "".into(), code: "    indented\n    # code\n".into(), closing: "".into() },
+                &Node::Html("<div>\n# Not a heading either\n</div>\n".into()),
+                &Node::Heading { level: 2, title: "Closed `code`".into() },
+            ],
+            nodes
+        );
+
+        let lines: Vec<usize> = document.numbered_nodes().map(|(line, _)| line).collect();
+        assert_eq!(vec![1, 3, 4, 4, 4, 4, 4, 4, 6], lines[..9].to_vec());
+        assert!(document.numbered_nodes().any(|(line, node)| line == 26 && node == &Node::Heading { level: 2, title: "Closed `code`".into() }));
+
+        let written = document.to_string();
+        assert!(written.starts_with("# Setext\n\n\nSome `a[i](#x)`"));
+        assert!(written.ends_with("> # Quoted\n\n## Closed `code`\n\n"));
+        assert_eq!(
+            CHAPTER.replace("Setext\n======\n", "# Setext\n\n").replace("## Closed `code` ##\n", "## Closed `code`\n\n"),
+            written
+        );
+    }
+
+    #[test]
+    fn links_and_footnotes() {
+        let chapter = indoc!("
+            See [a `b`](../x.md#y \"Y\"), [c][d], <https://e> and ![f](<g.png>)[^1].
+
+            ```
+            [not](#a-link) [^nor]
+            [a]: #definition
+            ```
+
+            [d]: http://d \"D\"
+            [^1]: Note [h](i.html).
+            ");
+        let document = Document::parse(chapter);
+        let nodes: Vec<(usize, &Node)> = document
+            .numbered_nodes()
+            .filter(|(_, node)| match node { Node::Text(_) | Node::InlineCode(_) | Node::CodeBlock { .. } => false, _ => true })
+            .collect();
+
+        assert_eq!(
+            vec![
+                (1, &Node::Destination("../x.md#y".into())),
+                (1, &Node::Label { name: "d".into(), definition: false }),
+                (1, &Node::Destination("https://e".into())),
+                (1, &Node::Destination("g.png".into())),
+                (1, &Node::Footnote { name: "1".into(), definition: false }),
+                (8, &Node::Label { name: "d".into(), definition: true }),
+                (8, &Node::Destination("http://d".into())),
+                (9, &Node::Footnote { name: "1".into(), definition: true }),
+                (9, &Node::Destination("i.html".into())),
+            ],
+            nodes
+        );
+        assert_eq!(chapter, document.to_string());
+
+        let mut document = Document::parse("## The [`?` operator](#question) and <https://e>\n\nSee [it](#question).\n");
+        assert_eq!(vec![(1, "#question"), (1, "https://e"), (3, "#question")], document.destinations());
+        document.map_destinations(|target| target.replace("#", "#sec--"));
+        assert_eq!("## The [`?` operator](#sec--question) and <https://e>\n\n\nSee [it](#sec--question).\n", document.to_string());
+    }
+
+    #[test]
+    fn fences() {
+        assert_eq!(Some(("", "```", "{rust,ignore}")), fence_parts("```{rust,ignore}"));
+        assert_eq!(Some(("  ", "~~~~", "rust")), fence_parts("  ~~~~ rust"));
+        assert_eq!(None, fence_parts("`` not a fence"));
+        assert!(is_closing_fence("````\n", "```"));
+        assert!(!is_closing_fence("```\n", "````"));
+        assert!(!is_closing_fence("~~~\n", "```"));
+
+        let attributes = |info| info_attributes(info).collect::<Vec<&str>>();
+        assert_eq!(vec!["rust", "ignore"], attributes("rust,ignore"));
+        assert_eq!(vec!["rust", "should_panic"], attributes("{.rust .should_panic}"));
+        assert_eq!(Vec::<&str>::new(), attributes(""));
+    }
+
+    #[test]
+    fn hidden_lines() {
+        assert_eq!(Some("fn main() {"), hidden_line("# fn main() {"));
+        assert_eq!(Some("x"), hidden_line("#\u{a0}x"));
+        assert_eq!(Some(""), hidden_line("    #"));
+        assert_eq!(None, hidden_line("#[test]"));
+        assert_eq!(None, hidden_line("x # y"));
+    }
+}
@@ -1,90 +1,454 @@
+pub mod examples;
+pub mod links;
+pub mod markdown;
+mod paths;
 mod text_traits;
 mod toc;
+pub mod transform;
 
 use std::{
-    io,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
     path::Path,
 };
 
 use failure::Error;
-use ::aux::file;
+use ::aux::{config::Book, file};
+use ::metadata::BookMetadata;
+use self::{
+    markdown::{Document, Node},
+    transform::{Chapter, HiddenLines, Registry, Transform},
+};
+
+/// A book or omnibus volume ready for rendering
+pub struct Prepared {
+    pub metadata: BookMetadata,
+    pub markdown: String,
+    /// The chapters as `SUMMARY.md` nests them, for navigation
+    pub contents: Vec<Contents>,
+    /// Every link to an anchor inside the book or to an external URL
+    pub links: Vec<links::Link>,
+}
+
+/// A chapter in the navigation of a book or volume
+#[derive(Debug, PartialEq)]
+pub struct Contents {
+    /// The id of the chapter's heading, `part--…` for the parts of a book
+    /// and `book--…` for the books of an omnibus
+    pub id: String,
+    pub children: Vec<Contents>,
+}
+
+/// How a book's chapters are prepared: where its markdown goes, alone
+/// or as one part of an omnibus, and where its documentation links point
+struct BookContext<'a> {
+    /// Prepended to section and reference ids, empty for a single book
+    namespace: &'a str,
+    /// Heading levels above the book's own chapters
+    level: usize,
+    doc_urls: HashMap<String, String>,
+    /// The passes chapters go through, in order
+    transforms: &'a [&'a dyn Transform],
+    hidden_lines: HiddenLines,
+}
+
+impl<'a> BookContext<'a> {
+    fn single(metadata: &BookMetadata, transforms: &'a [&'a dyn Transform], hidden_lines: HiddenLines) -> Self {
+        BookContext { namespace: "", level: 0, doc_urls: metadata.doc_urls(), transforms, hidden_lines }
+    }
+
+    /// Books in an omnibus link to their own `sec--{namespace}` ids
+    fn namespace_target(&self, target: &str) -> String {
+        if target.starts_with("#sec--") {
+            format!("#sec--{}{}", self.namespace, &target["#sec--".len()..])
+        } else {
+            target.to_string()
+        }
+    }
+
+    /// The heading `title` with its `{#sec--…}` id namespaced
+    fn namespace_title(&self, title: &str) -> String {
+        match title.rfind("{#sec--") {
+            Some(id) => {
+                let id = id + "{#sec--".len();
+                format!("{}{}{}", &title[..id], self.namespace, &title[id..])
+            }
+            None => title.to_string(),
+        }
+    }
+
+    /// Namespaces the ids of the headings and the links in `document`
+    fn namespace_ids(&self, document: &mut Document) {
+        if self.namespace.is_empty() { return }
+
+        document.map_destinations(|target| self.namespace_target(target));
+        for node in document.nodes_mut() {
+            if let Node::Heading { title, .. } = node {
+                *title = Cow::Owned(self.namespace_title(title));
+            }
+        }
+    }
+
+    /// The id of the heading `create_chapter` gives `filename`
+    fn section_id(&self, filename: &str) -> String {
+        format!("sec--{}{}", self.namespace, paths::slug(filename))
+    }
+
+    /// The id of the heading `aggregate` gives the part `title`
+    fn part_id(&self, title: &str) -> String {
+        format!("part--{}{}", self.namespace, ::render::title_identifier(title))
+    }
+
+    fn heading(&self, level: usize) -> String {
+        "#".repeat(self.level + level)
+    }
+}
+
+/// Resolves the links of the chapter `filename`, relative to `path_prefix`,
+/// and points links to its `headings` at their chapter-qualified ids
+fn prepare_links(document: &mut Document, filename: &str, path_prefix: &Path, headings: &HashSet<String>) {
+    use self::text_traits::{adjust::qualify_local_links, relative::resolve_relative_paths};
 
-fn prepare_chapter_md(markdown: String, title_bump: usize, ref_prefix: &str) -> String {
-    use self::text_traits::{
-        adjust::AdjustExt,
-        normalize::NormalizeExt,
-        references::MdRefsExt,
+    resolve_relative_paths(document, paths::parent(filename), path_prefix);
+    qualify_local_links(document, &paths::section_id(filename), headings);
+}
+
+/// Runs the chapter `filename` through the `context`'s transforms,
+/// the links of the result go to `links`
+fn prepare_chapter_md(
+    markdown: &str,
+    title_bump: usize,
+    ref_prefix: &str,
+    filename: &str,
+    path_prefix: &Path,
+    context: &BookContext,
+    links: &mut Vec<links::Link>
+) -> Result<String, Error> {
+    use self::text_traits::adjust::heading_ids;
+
+    let mut document = Document::parse(markdown);
+    let chapter = Chapter {
+        filename,
+        path_prefix,
+        title_bump,
+        ref_prefix,
+        headings: heading_ids(&document),
+        doc_urls: &context.doc_urls,
+        hidden_lines: context.hidden_lines,
     };
 
-    markdown
-        .increase_title_level(title_bump)
-        .remove_markdown_file_title()
-        .prefix_refs_with(ref_prefix)
-        .normalize()
+    for transform in context.transforms {
+        transform
+            .apply(&mut document, &chapter)
+            .map_err(|e| format_err!("Transform '{}' failed on {}: {}", transform.name(), filename, e))?;
+    }
+    context.namespace_ids(&mut document);
+
+    links.extend(links::chapter_links(&document, path_prefix.join(filename)));
+
+    Ok(document.to_string())
 }
 
-fn create_introduction(path_prefix: &Path) -> io::Result<String> {
+fn create_introduction(
+    path_prefix: &Path,
+    context: &BookContext,
+    links: &mut Vec<links::Link>
+) -> Result<String, Error> {
     println!("  README.md");
     let mut markdown = String::with_capacity(256);
 
-    markdown.push_str("\n\n# Introduction\n\n");
+    markdown.push_str(&format!("\n\n{} Introduction\n\n", context.heading(1)));
     let readme_raw = file::to_string(path_prefix.join("README.md"))?;
-    let introduction = prepare_chapter_md(readme_raw, 1, "readme");
+    let introduction = prepare_chapter_md(
+        &readme_raw,
+        context.level + 1,
+        &format!("{}readme", context.namespace),
+        "README.md",
+        path_prefix,
+        context,
+        links
+    )?;
     markdown.push_str(&introduction);
 
     Ok(markdown)
 }
 
-fn create_chapter(chapter: &toc::Chapter, path_prefix: &Path) -> io::Result<String> {
-    println!("  {}", chapter.filename);
+fn create_chapter(
+    node: &toc::Node,
+    filename: &str,
+    path_prefix: &Path,
+    context: &BookContext,
+    number_sections: bool,
+    links: &mut Vec<links::Link>
+) -> Result<String, Error> {
+    println!("  {}", filename);
+    let chapter = node.chapter;
     let mut markdown = String::with_capacity(512);
 
     // Markdown chapter title
     markdown.push_str("\n\n");
-    for _ in 0..=chapter.nest_level { markdown.push('#') }
+    markdown.push_str(&context.heading(chapter.nest_level + 1));
     markdown.push(' ');
-    markdown.push_str(&chapter.header);
+    if let (true, Some(number)) = (number_sections, &node.number) {
+        markdown.push_str(&format!("{} ", number));
+    }
+    markdown.push_str(&context.namespace_title(&chapter.header));
     markdown.push('\n');
 
-    let chapter_raw = file::to_string(path_prefix.join(&chapter.filename))?;
-
+    let chapter_raw = file::to_string(path_prefix.join(filename))?;
     let chapter_contents = prepare_chapter_md(
-        chapter_raw,
-        chapter.nest_level + 1,
-        &chapter.filename
-    );
+        &chapter_raw,
+        context.level + chapter.nest_level + 1,
+        &format!("{}{}", context.namespace, filename),
+        filename,
+        path_prefix,
+        context,
+        links
+    )?;
 
     markdown.push_str(&chapter_contents);
 
     Ok(markdown)
 }
 
+/// The navigation of `root` and the chapters below it, each under its closest
+/// ancestor in the book; drafts are not in the book, so they are left out
+fn contents(tree: &toc::ChapterTree, root: toc::NodeId, context: &BookContext) -> Vec<Contents> {
+    // Entries that may still get children, innermost last
+    let mut open: Vec<(toc::NodeId, Contents)> = Vec::new();
+    let mut closed = Vec::new();
+    let close = |open: &mut Vec<(toc::NodeId, Contents)>, closed: &mut Vec<Contents>| {
+        if let Some((_, entry)) = open.pop() {
+            match open.last_mut() {
+                Some((_, parent)) => parent.children.push(entry),
+                None => closed.push(entry),
+            }
+        }
+    };
+
+    for id in tree.depth_first(root) {
+        let node = tree.get(id);
+        let filename = match node.chapter.filename {
+            Some(ref filename) => filename,
+            None => continue,
+        };
+
+        let mut parent = node.parent;
+        while let Some(draft) = parent.filter(|&parent| tree.get(parent).chapter.is_draft()) {
+            parent = tree.get(draft).parent;
+        }
+        while !open.is_empty() && open.last().map(|&(id, _)| id) != parent {
+            close(&mut open, &mut closed);
+        }
+        open.push((id, Contents { id: context.section_id(filename), children: Vec::new() }));
+    }
+    while !open.is_empty() {
+        close(&mut open, &mut closed);
+    }
+
+    closed
+}
+
+/// The navigation of the whole `tree`, chapters listed under a part are
+/// grouped under an entry for it
+fn book_contents(tree: &toc::ChapterTree, context: &BookContext) -> Vec<Contents> {
+    let mut book = Vec::new();
+    let mut part = None;
+
+    for &id in tree.roots() {
+        let node = tree.get(id);
+        if node.part != part {
+            if let Some(title) = node.part {
+                book.push(Contents { id: context.part_id(title), children: Vec::new() });
+            }
+            part = node.part;
+        }
+
+        let chapters = contents(tree, id, context);
+        match part {
+            Some(_) => book.last_mut().unwrap().children.extend(chapters),
+            None => book.extend(chapters),
+        }
+    }
+
+    book
+}
+
+fn aggregate(
+    path_prefix: &Path,
+    context: &BookContext,
+    number_sections: bool,
+    links: &mut Vec<links::Link>
+) -> Result<(String, Vec<Contents>), Error> {
+    let mut book = String::new();
+
+    let toc = toc::TableOfContents::load(path_prefix)?;
+
+    let tree = toc::ChapterTree::new(&toc);
+    let mut part = None;
+
+    // Unless `SUMMARY.md` lists it as a chapter
+    if tree.find(toc::INTRODUCTION).is_none() {
+        book.push_str(&create_introduction(path_prefix, context, links)?);
+    }
+
+    // Depth first is document order
+    let chapters = tree.roots().iter().flat_map(|&root| tree.depth_first(root));
+    for node in chapters.map(|id| tree.get(id)) {
+        if node.part != part {
+            if let Some(title) = node.part {
+                book.push_str(&format!("\n\n{} {} {{#{}}}\n", context.heading(1), title, context.part_id(title)));
+            }
+            part = node.part;
+        }
+
+        match node.chapter.filename {
+            Some(ref filename) => book.push_str(
+                &create_chapter(node, filename, path_prefix, context, number_sections, links)?
+            ),
+            None => println!("  {} (draft, skipped)", node.chapter.title),
+        }
+    }
+
+    Ok((book, book_contents(&tree, context)))
+}
+
+/// The book's metadata and its aggregated markdown, its chapters run through
+/// the `transforms`; with `number_sections` chapter headings start with their
+/// section number
 pub fn create_book<P: AsRef<Path>>(
     path_prefix: P,
     meta_path: P,
-    release_date: &str
-) -> Result<String, Error> {
-    let path_prefix = path_prefix.as_ref();
-    let mut book = String::new();
-    
-    book.push_str(&(
-        file::to_string(meta_path)?
-            .replace("{release_date}", release_date) + "\n"
-    ));
-
-    book.push_str(&create_introduction(path_prefix)?);
-
-    file::to_string(path_prefix.join("SUMMARY.md"))?
-        .parse::<toc::TableOfContents>()?
-        .into_iter()
-        .map(|chapter| create_chapter(&chapter, path_prefix))
-        .map(|contents| contents.map(|c| book.push_str(&c)))
-        .collect::<io::Result<()>>()?;
-
-    Ok(book)
+    release_date: &str,
+    number_sections: bool,
+    transforms: &[&dyn Transform],
+    hidden_lines: HiddenLines
+) -> Result<Prepared, Error> {
+    let metadata = BookMetadata::load(meta_path, release_date)?;
+    let mut links = Vec::new();
+    let context = BookContext::single(&metadata, transforms, hidden_lines);
+    let (markdown, contents) = aggregate(path_prefix.as_ref(), &context, number_sections, &mut links)?;
+
+    Ok(Prepared { metadata, markdown, contents, links })
+}
+
+/// The book's markdown with code lines longer than `width` characters wrapped,
+/// for formats read on small screens
+pub fn wrap_code(markdown: &str, width: usize) -> String {
+    let mut document = Document::parse(markdown);
+    text_traits::adjust::wrap_code_blocks(&mut document, width);
+    document.to_string()
+}
+
+/// One volume with a part per book, its metadata read from `meta_path`
+/// or combined from the books' metadata. Each book's chapters go through
+/// the transforms it chose from the `registry`.
+pub fn create_omnibus(
+    title: &str,
+    meta_path: Option<&str>,
+    books: &[&Book],
+    release_date: &str,
+    number_sections: bool,
+    registry: &Registry
+) -> Result<Prepared, Error> {
+    let mut volume = String::new();
+    let mut contents = Vec::new();
+    let mut links = Vec::new();
+    let mut books_metadata = Vec::with_capacity(books.len());
+
+    for book in books {
+        println!(" {}", book.prefix);
+        let metadata = BookMetadata::load(&book.meta, release_date)?;
+        let transforms = registry.pipeline(book.transforms.as_ref().map(Vec::as_slice))?;
+        // Namespaced chapter ids must not contain `--`, which separates headings
+        let namespace = format!("{}.", book.prefix);
+
+        volume.push_str(&format!("\n\n# {} {{#book--{}}}\n", metadata.title, book.prefix));
+        let (markdown, children) = aggregate(
+            Path::new(&book.source),
+            &BookContext {
+                namespace: &namespace,
+                level: 1,
+                doc_urls: metadata.doc_urls(),
+                transforms: &transforms,
+                hidden_lines: book.hidden_lines,
+            },
+            number_sections,
+            &mut links
+        )?;
+        volume.push_str(&markdown);
+        contents.push(Contents { id: format!("book--{}", book.prefix), children });
+        books_metadata.push(metadata);
+    }
+
+    let metadata = match meta_path {
+        Some(path) => BookMetadata::load(path, release_date)?,
+        None => BookMetadata::combine(title, &books_metadata)?,
+    };
+
+    Ok(Prepared { metadata, markdown: volume, contents, links })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn omnibus_context() {
+        let context = BookContext {
+            namespace: "nomicon.",
+            level: 1,
+            doc_urls: HashMap::new(),
+            transforms: &[],
+            hidden_lines: HiddenLines::Remove,
+        };
+        assert_eq!("##", context.heading(1));
+        assert_eq!("Data {#sec--nomicon.data}", context.namespace_title("Data {#sec--data}"));
+
+        let namespaced = |context: &BookContext, markdown| {
+            let mut document = Document::parse(markdown);
+            context.namespace_ids(&mut document);
+            document.to_string()
+        };
+        assert_eq!(
+            "## Data {#sec--nomicon.data}\n\nSee [Repr](#sec--nomicon.repr).\n\n```\n#sec--code\n```\n",
+            namespaced(&context, "## Data {#sec--data}\nSee [Repr](#sec--repr).\n\n```\n#sec--code\n```\n")
+        );
+
+        let single = BookContext::single(&BookMetadata::default(), &[], HiddenLines::Remove);
+        assert_eq!("See [Data](#sec--data).\n", namespaced(&single, "See [Data](#sec--data).\n"));
+    }
+
+    #[test]
+    fn parts() {
+        use std::{env, fs};
+
+        let source = env::temp_dir().join(format!("compile-trpl-parts-{}", ::std::process::id()));
+        fs::create_dir_all(&source).unwrap();
+        let write = |path: &str, text: &str| fs::write(source.join(path), text).unwrap();
+        write("SUMMARY.md", "# Summary\n\n- [A](a.md)\n\n# Part I\n\n- [B](b.md)\n    - [Draft]()\n        - [C](c.md)\n");
+        write("README.md", "Welcome.\n");
+        for chapter in &["a.md", "b.md", "c.md"] { write(chapter, "Text.\n") }
+
+        let context = BookContext::single(&BookMetadata::default(), &[], HiddenLines::Remove);
+        let aggregated = aggregate(&source, &context, false, &mut Vec::new());
+        fs::remove_dir_all(&source).unwrap();
+        let (markdown, contents) = aggregated.unwrap();
+
+        let headings: Vec<&str> = markdown.lines().filter(|line| line.starts_with('#')).collect();
+        assert_eq!(
+            vec!["# Introduction", "# A {#sec--a}", "# Part I {#part--part-i}", "# B {#sec--b}", "### C {#sec--c}"],
+            headings
+        );
+
+        let chapter = |id: &str, children| Contents { id: id.into(), children };
+        assert_eq!(
+            vec![
+                chapter("sec--a", vec![]),
+                // The draft's chapter goes to its parent
+                chapter("part--part-i", vec![chapter("sec--b", vec![chapter("sec--c", vec![])])]),
+            ],
+            contents
+        );
+    }
 }
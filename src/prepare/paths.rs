@@ -0,0 +1,53 @@
+//! Paths inside a book are always `/`-separated and relative to the source directory
+
+/// The directory part of `path`, empty for top-level files
+pub fn parent(path: &str) -> &str {
+    path.rfind('/').map(|end| &path[..end]).unwrap_or("")
+}
+
+/// `relative` resolved against `base`, `None` if it leaves `base`'s root
+pub fn join(base: &str, relative: &str) -> Option<String> {
+    let mut components: Vec<&str> = Vec::new();
+    for component in base.split('/').chain(relative.split('/')) {
+        match component {
+            "" | "." => {},
+            ".." => { components.pop()?; }
+            _ => components.push(component),
+        }
+    }
+    Some(components.join("/"))
+}
+
+/// Chapter ids are derived from the chapter's file: `ch03/ownership.md` becomes `ch03-ownership`
+pub fn slug(filename: &str) -> String {
+    let stem = match filename.rfind('.') {
+        Some(dot) if dot > filename.rfind('/').map(|slash| slash + 1).unwrap_or(0) => &filename[..dot],
+        _ => filename,
+    };
+    stem.replace('/', "-")
+}
+
+/// The id of a chapter's header, headings inside the chapter append `--{heading}`
+pub fn section_id(filename: &str) -> String {
+    format!("sec--{}", slug(filename))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn book_paths() {
+        assert_eq!("ch03", parent("ch03/ownership.md"));
+        assert_eq!("", parent("ownership.md"));
+
+        assert_eq!(Some("ch04/img/x.png".to_string()), join("ch03", "../ch04/img/x.png"));
+        assert_eq!(Some("a.md".to_string()), join("", "./a.md"));
+        assert_eq!(None, join("", "../std/index.html"));
+
+        assert_eq!("ch03-ownership", slug("ch03/ownership.md"));
+        assert_eq!("guessing-game", slug("guessing-game.md"));
+        assert_eq!("v1.0-notes", slug("v1.0/notes"));
+        assert_eq!("sec--ch03-ownership", section_id("ch03/ownership.md"));
+    }
+}
@@ -1,71 +1,150 @@
-use super::patterns::{CODE_BLOCK_TOGGLE, reg};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+use super::patterns::reg;
+use super::super::markdown::{hidden_line, Document, Node};
 
-pub trait AdjustExt: AsRef<str> + Sized {
-    fn line_break_at(self, max_len: usize, separator: &str) -> String {
-        // TODO use Cow<str>
-        if self.as_ref().len() <= max_len { return self.as_ref().to_string() }
-
-        let adjusted_len = max_len - separator.chars().count();
-
-        let mut collector = String::with_capacity(self.as_ref().len());
-        let (first, rest) = self.as_ref().split_at(max_len);
-        
-        collector.push_str(first);
-        collector.push('\n');
-
-        // because str::chunks() does not exist
-        let rest_chars: Vec<char> = rest.chars().collect();
-        let rest_lines = rest_chars.as_slice().chunks(adjusted_len);
-        
-        for line in rest_lines {
-            collector.push_str(separator);
-            // because String::extend does not exist
-            for chr in line { collector.push(*chr) }
-            collector.push('\n');
-        }
+/// Hands out heading identifiers unique within one chapter, like Pandoc's `auto_identifiers`
+#[derive(Default)]
+struct HeadingIds(HashMap<String, usize>);
+
+impl HeadingIds {
+    /// `None` for titles that end with an explicit `{#id}`
+    fn next(&mut self, title: &str) -> Option<String> {
+        lazy_static_regex!(HEADER_ID, reg::mdfile::HEADER_ID);
+        if HEADER_ID.is_match(title) { return None }
 
-        collector.pop(); // Remove extra newline
-        collector
+        let identifier = ::render::title_identifier(title);
+        let count = self.0.entry(identifier.clone()).or_insert(0);
+        *count += 1;
+
+        Some(match *count {
+            1 => identifier,
+            n => format!("{}-{}", identifier, n - 1),
+        })
     }
+}
+
+/// The identifiers of a chapter's headings that `increase_title_level` qualifies
+pub fn heading_ids(document: &Document) -> HashSet<String> {
+    let mut ids = HeadingIds::default();
+
+    document
+        .nodes()
+        .filter_map(|node| match node {
+            Node::Heading { title, .. } => ids.next(title),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Points `#heading` links at the qualified ids of the chapter's `headings`,
+/// other fragments like raw HTML ids are left alone
+pub fn qualify_local_links(document: &mut Document, section: &str, headings: &HashSet<String>) {
+    document.map_destinations(|target| if target.starts_with('#') && headings.contains(&target[1..]) {
+        format!("#{}--{}", section, &target[1..])
+    } else {
+        target.to_string()
+    });
+}
+
+/// Also gives every heading without an explicit id the id
+/// `{section}--{pandoc id}`, so they do not collide across chapters
+pub fn increase_title_level(document: &mut Document, increase: usize, section: &str) {
+    let mut ids = HeadingIds::default();
 
-    // TODO: Why is this code so complex
-    //       Just put more '#'s at the beginning
-    fn increase_title_level(self, increase: usize) -> String {
-        lazy_static_regex!(HEADER_PATTERN, reg::mdfile::HEADER);
-
-        let mut in_code_block = false;
-        let mut collector = String::with_capacity(self.as_ref().len());
-
-        for line in self.as_ref().lines() {
-            if in_code_block && !line.starts_with(CODE_BLOCK_TOGGLE) {
-                collector.push_str(line);
-            } else if let Some(headline) = HEADER_PATTERN.captures(line) {
-                // '#' is always 1 byte, so .len() is safe to use.
-                let old_level = headline.name("level").unwrap().as_str().len();
-                let new_level = old_level + increase - 1;
-
-                collector.push_str(&format!(
-                    "{empty:#^num_hashes$} {title}\n",
-                    empty = "",
-                    num_hashes = new_level,
-                    title = headline.name("title").unwrap().as_str()
-                ))
-            } else {
-                collector.push_str(line);
-                if line.starts_with(CODE_BLOCK_TOGGLE) {
-                    in_code_block = !in_code_block
-                }
-            };
-
-            collector.push('\n');
+    for node in document.nodes_mut() {
+        if let Node::Heading { level, title } = node {
+            *level += increase - 1;
+            if let Some(id) = ids.next(title) {
+                *title = Cow::Owned(format!("{} {{#{}--{}}}", title, section, id));
+            }
         }
+    }
+}
+
+/// Characters long code lines are preferably broken after
+const BREAK_AFTER: &str = ",;:.)]}([{+=|&/>";
+
+/// Marks the continuation of wrapped code lines
+const CONTINUATION: &str = "↳ ";
 
-        collector
+/// Wraps the lines of fenced code blocks longer than `width` characters.
+/// Continuations of hidden `# ` lines stay hidden.
+pub fn wrap_code_blocks(document: &mut Document, width: usize) {
+    for node in document.nodes_mut() {
+        if let Node::CodeBlock { fence, code, .. } = node {
+            if fence.is_empty() || code.lines().all(|line| line.chars().count() <= width) { continue }
+
+            let wrapped = code
+                .split_terminator('\n')
+                .map(|line| match hidden_line(line) {
+                    _ if line.chars().count() <= width => line.to_string(),
+                    Some(text) => {
+                        let marker = &line[..line.len() - text.len()];
+                        text.line_break_at(width.saturating_sub(marker.chars().count()), CONTINUATION)
+                            .lines()
+                            .map(|part| format!("{}{}", marker, part))
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    }
+                    None => line.line_break_at(width, CONTINUATION),
+                })
+                .fold(String::with_capacity(code.len()), |acc, line| acc + &line + "\n");
+            *code = Cow::Owned(wrapped);
+        }
+    }
+}
+
+/// Drops the `% title` line Pandoc reads as the document title, the
+/// nodes after it keep their source lines
+pub fn remove_file_title(document: &mut Document) {
+    match document.nodes().next() {
+        Some(Node::Text(text)) if text.starts_with("% ") => {},
+        _ => return,
+    }
+
+    // The title may have inline code or links, it ends with the first line break of prose
+    for node in document.nodes_mut() {
+        match node {
+            Node::Text(text) if text.contains('\n') => {
+                let rest = text[text.find('\n').unwrap() + 1..].to_string();
+                *text = Cow::Owned(rest);
+                break;
+            },
+            _ => *node = Node::Text(Cow::Borrowed("")),
+        }
     }
+}
+
+pub trait AdjustExt: AsRef<str> + Sized {
+    /// Breaks the line into lines of at most `max_len` characters, the
+    /// following ones starting with `separator`. Breaks go after whitespace
+    /// or punctuation where the second half of a line has any.
+    fn line_break_at(self, max_len: usize, separator: &str) -> String {
+        let line = self.as_ref();
+        let mut rest = line;
+        let mut limit = max_len.max(1);
+        let mut lines = Vec::new();
+
+        while rest.chars().count() > limit {
+            let end = rest.char_indices().nth(limit).map_or(rest.len(), |(i, _)| i);
+            let break_at = rest[..end]
+                .char_indices()
+                .skip(limit / 2 + 1)
+                .filter(|&(_, c)| c.is_whitespace() || BREAK_AFTER.contains(c))
+                .map(|(i, c)| i + c.len_utf8())
+                .last()
+                .unwrap_or(end);
+
+            lines.push(&rest[..break_at]);
+            rest = &rest[break_at..];
+            limit = max_len.saturating_sub(separator.chars().count()).max(1);
+        }
+        lines.push(rest);
 
-    fn remove_markdown_file_title(self) -> String {
-        lazy_static_regex!(FILE_TITLE, reg::mdfile::TITLE);
-        FILE_TITLE.replace(self.as_ref(), "").to_string()
+        lines.join(&format!("\n{}", separator))
     }
 }
 
@@ -83,10 +162,10 @@ mod tests {
         ");
 
     const CORRECT_SPLIT: &str = indoc!("
-        markdown+grid_tables+pipe_tables+raw_html+implicit_figures+footnotes+intraword_u
-        ↳ nderscores+auto_identifiers-inline_code_attributesmarkdown+grid_tables+pipe_ta
-        ↳ bles+raw_html+implicit_figures+footnotes+intraword_underscores+auto_identifier
-        ↳ s-inline_code_attributes
+        markdown+grid_tables+pipe_tables+raw_html+implicit_figures+footnotes+
+        ↳ intraword_underscores+auto_identifiers-inline_code_attributesmarkdown+
+        ↳ grid_tables+pipe_tables+raw_html+implicit_figures+footnotes+
+        ↳ intraword_underscores+auto_identifiers-inline_code_attributes
         ");
 
     #[test]
@@ -95,13 +174,111 @@ mod tests {
             CORRECT_SPLIT,
             LONG_LINE.line_break_at(80, "↳ ")
         );
+        assert_eq!("äöüäöü\n↳ äöüä\n↳ öü", "äöüäöüäöüäöü".line_break_at(6, "↳ "));
+        assert_eq!("let x = \n↳ vec![1, \n↳ 2];", "let x = vec![1, 2];".line_break_at(10, "↳ "));
     }
 
     #[test]
-    fn markdown_file_title() {
+    fn wrapped_code() {
+        let mut document = Document::parse(indoc!("
+            A long line of prose that is left alone.
+
+            ```rust
+            # fn long_hidden_function() {}
+            #\u{a0}fn long_hidden_function() {}
+            let short = 1;
+            let long = some_function(argument);
+            ```
+
+                indented_code_is_left_alone();
+            "));
+        wrap_code_blocks(&mut document, 24);
         assert_eq!(
-            "\nthing\nand\nthong",
-            "% title\n\nthing\nand\nthong".remove_markdown_file_title()
+            indoc!("
+                A long line of prose that is left alone.
+
+                ```rust
+                # fn long_hidden_functio
+                # ↳ n() {}
+                #\u{a0}fn long_hidden_functio
+                #\u{a0}↳ n() {}
+                let short = 1;
+                let long = some_function
+                ↳ (argument);
+                ```
+
+                    indented_code_is_left_alone();
+                "),
+            document.to_string()
         );
     }
+
+    const CHAPTER: &str = indoc!("
+        # Examples
+
+        See [below](#examples-1), [the code](#code-try-def) and [above](#examples).
+
+        ```rust
+        # fn hidden() {}
+        ```
+
+        ~~~
+        ## Not a heading
+        ~~~
+
+        ## Examples
+
+        ### Custom {#custom-id}
+
+        [try]: #examples
+        ");
+
+    const QUALIFIED: &str = indoc!("
+        ## Examples {#sec--errors--examples}
+
+        See [below](#sec--errors--examples-1), [the code](#code-try-def) and [above](#sec--errors--examples).
+
+        ```rust
+        # fn hidden() {}
+        ```
+
+        ~~~
+        ## Not a heading
+        ~~~
+
+        ### Examples {#sec--errors--examples-1}
+
+        #### Custom {#custom-id}
+
+        [try]: #sec--errors--examples
+        ");
+
+    #[test]
+    fn heading_identifiers() {
+        let mut document = Document::parse(CHAPTER);
+        let headings = heading_ids(&document);
+        let mut sorted: Vec<&str> = headings.iter().map(String::as_str).collect();
+        sorted.sort();
+        assert_eq!(vec!["examples", "examples-1"], sorted);
+
+        qualify_local_links(&mut document, "sec--errors", &headings);
+        increase_title_level(&mut document, 2, "sec--errors");
+        let qualified = document.to_string().replace("\n\n\n", "\n\n");
+        assert_eq!(QUALIFIED, qualified);
+    }
+
+    #[test]
+    fn markdown_file_title() {
+        let mut document = Document::parse("% title\n\nthing\nand\nthong");
+        remove_file_title(&mut document);
+        assert_eq!("\nthing\nand\nthong", document.to_string());
+
+        let mut document = Document::parse("% `const` and [`static`](a.md)\n\nthing\n");
+        remove_file_title(&mut document);
+        assert_eq!("\nthing\n", document.to_string());
+
+        let mut document = Document::parse("thing\n% not a title\n");
+        remove_file_title(&mut document);
+        assert_eq!("thing\n% not a title\n", document.to_string());
+    }
 }
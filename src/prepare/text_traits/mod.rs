@@ -1,5 +1,5 @@
-mod patterns;
+pub mod patterns;
 pub mod normalize;
 pub mod references;
-pub mod str_iter;
+pub mod relative;
 pub mod adjust;
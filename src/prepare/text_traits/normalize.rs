@@ -1,27 +1,36 @@
-use super::patterns::{CODE_BLOCK_TOGGLE, reg};
-use std::borrow::Cow;
-
-fn normalize_links(s: &str) -> String {
-    use self::reg::links::{src, replace};
-
-    lazy_static_regex!(SEC_LINK,    src::SEC       );
-    lazy_static_regex!(SEC_REF,     src::SEC_REF   );
-    lazy_static_regex!(SUBSEC_LINK, src::SUBSEC    );
-    lazy_static_regex!(SUBSEC_REF,  src::SUBSEC_REF);
-
-    let output = s
-        .replace(r"../std",       r"http://doc.rust-lang.org/std"      )
-        .replace(r"../reference", r"http://doc.rust-lang.org/reference")
-        .replace(r"../rustc",     r"http://doc.rust-lang.org/rustc"    )
-        .replace(r"../syntax",    r"http://doc.rust-lang.org/syntax"   )
-        .replace(r"../book",      r"http://doc.rust-lang.org/book"     )
-        .replace(r"../adv-book",  r"http://doc.rust-lang.org/adv-book" )
-        .replace(r"../core",      r"http://doc.rust-lang.org/core"     );
-    
-    let output = SEC_LINK   .replace_all(&output, replace::SEC       );
-    let output = SEC_REF    .replace_all(&output, replace::SEC_REF   );
-    let output = SUBSEC_LINK.replace_all(&output, replace::SUBSEC    );
-    let output = SUBSEC_REF .replace_all(&output, replace::SUBSEC_REF);
+use super::patterns::reg;
+use super::super::{
+    markdown::{fence_parts, hidden_line, info_attributes, Document, Node},
+    paths,
+    transform::HiddenLines,
+};
+use regex::Captures;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+};
+
+/// `doc_urls` maps the `name` of `../name` links to the documentation's URL
+fn normalize_link(target: &str, doc_urls: &HashMap<String, String>) -> String {
+    use self::reg::links::src;
+
+    lazy_static_regex!(DOC_LINK,    src::DOC   );
+    lazy_static_regex!(SEC_LINK,    src::SEC   );
+    lazy_static_regex!(SUBSEC_LINK, src::SUBSEC);
+
+    let output = DOC_LINK.replace(target, |capture: &Captures| {
+        match doc_urls.get(&capture["name"]) {
+            Some(url) => url.clone(),
+            None => capture[0].to_string(),
+        }
+    });
+
+    let output = SEC_LINK.replace(&output, |capture: &Captures| {
+        format!("#{}", paths::section_id(&capture["file"]))
+    });
+    let output = SUBSEC_LINK.replace(&output, |capture: &Captures| {
+        format!("#{}--{}", paths::section_id(&capture["file"]), &capture["subsection"])
+    });
 
     output.into_owned()
 }
@@ -32,54 +41,97 @@ fn normalize_math(s: &str) -> Cow<str> {
     SUPERSCRIPT.replace_all(s, reg::math::SUPERSCRIPT_REPLACE)
 }
 
-fn normalize_code_start(s: &str) -> String {
-    lazy_static_regex!(RUST_START,  reg::code::BLOCK_RUST);
-    lazy_static_regex!(HIDDEN_CODE, reg::code::BLOCK_HIDDEN);
-
-    let mut in_code_block = false;
-    let mut collector = String::with_capacity(s.len());
-
-    for line in s.lines() {
-        if in_code_block && HIDDEN_CODE.is_match(line) { continue; }
-        
-        if RUST_START.is_match(line) {
-            in_code_block = true;
-            collector.push_str("```rust");
-        } else {
-            collector.push_str(line);
-            if line.starts_with(CODE_BLOCK_TOGGLE) {
-                in_code_block = false;
+/// Marks Rust code blocks as `rust`, other attributes as classes, and drops
+/// their hidden lines unless `hidden_lines` keeps them, marked by a
+/// `hidden-grey` or `hidden-toggle` class for the renderers
+pub fn normalize_code_blocks(document: &mut Document, hidden_lines: HiddenLines) {
+    for node in document.nodes_mut() {
+        if let Node::CodeBlock { fence, code, .. } = node {
+            let (indent, marker, info) = match fence_parts(fence) {
+                Some((indent, marker, info)) if info.contains("rust") => (indent, marker, info),
+                _ => continue,
+            };
+            let has_hidden = code.lines().any(|line| hidden_line(line).is_some());
+
+            // Attributes like `ignore` become Pandoc classes, shown as badges
+            let mut classes: Vec<_> = info_attributes(info)
+                .filter(|attribute| *attribute != "rust")
+                .collect();
+            match hidden_lines {
+                HiddenLines::Grey if has_hidden => classes.push("hidden-grey"),
+                HiddenLines::Toggle if has_hidden => classes.push("hidden-toggle"),
+                _ => {}
             }
-        }
 
-        collector.push('\n');
+            let normalized = if classes.is_empty() {
+                format!("{}{}rust", indent, marker)
+            } else {
+                let classes: String = classes.iter().map(|class| format!(" .{}", class)).collect();
+                format!("{}{}{{.rust{}}}", indent, marker, classes)
+            };
+            *fence = Cow::Owned(normalized);
+
+            if hidden_lines == HiddenLines::Remove {
+                *code = Cow::Owned(
+                    code.split_terminator('\n')
+                        .filter(|line| hidden_line(line).is_none())
+                        .fold(String::with_capacity(code.len()), |acc, line| acc + line + "\n")
+                );
+            }
+        }
     }
-
-    collector
 }
 
-pub trait NormalizeExt: AsRef<str> + Sized {
-    fn normalize(self) -> String {
-        let output: Cow<str> = normalize_math(self.as_ref());
-        let output: String   = normalize_links(&output);
-        let output: String   = normalize_code_start(&output);
-
-        output
-    }
+/// Turns links to chapters into links to their sections, and points
+/// `../name` links at the documentation
+pub fn normalize(document: &mut Document, doc_urls: &HashMap<String, String>) {
+    document.map_prose(|text| normalize_math(text).into_owned());
+    document.map_destinations(|target| normalize_link(target, doc_urls));
 }
 
-impl<S> NormalizeExt for S where S: AsRef<str> {}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ::metadata::BookMetadata;
+
+    fn doc_urls() -> HashMap<String, String> { BookMetadata::default().doc_urls() }
+
+    fn normalized(markdown: &str, doc_urls: &HashMap<String, String>) -> String {
+        let mut document = Document::parse(markdown);
+        normalize(&mut document, doc_urls);
+        document.to_string()
+    }
 
     #[test]
     fn math() {
+        assert_eq!("123^1234^thing", normalized("123<sup>1234</sup>thing", &doc_urls()))
+    }
+
+    #[test]
+    fn links() {
         assert_eq!(
-            "123^1234^thing",
-            "123<sup>1234</sup>thing".normalize().trim(),
-        )
+            indoc!("
+                [Ownership](#sec--ch03-ownership), [Vectors](#sec--vectors)
+                and [Errors](#sec--error-handling--custom-errors).
+
+                [drop]: #sec--drop
+                [io]: http://doc.rust-lang.org/std/io/index.html
+                "),
+            normalized(indoc!("
+                [Ownership](ch03/ownership.md), [Vectors](vectors.html)
+                and [Errors](error-handling.md#custom-errors).
+
+                [drop]: drop.html
+                [io]: ../std/io/index.html
+                "), &doc_urls())
+        );
+
+        let mut urls = doc_urls();
+        urls.insert("std".into(), "https://doc.rust-lang.org/1.30.0/std".into());
+        assert_eq!(
+            "[Vec](https://doc.rust-lang.org/1.30.0/std/vec/struct.Vec.html), [x](../unknown/x.html)",
+            normalized("[Vec](../../std/vec/struct.Vec.html), [x](../unknown/x.html)", &urls)
+        );
     }
 
     const CODE_BLOCKS: &str = indoc!("
@@ -106,6 +158,19 @@ mod tests {
         # }
         let x = true;
         ```
+
+        ~~~ {rust}
+        # hidden
+        let y = 1;
+        ~~~
+
+        ````rust
+        ```
+        # fn main() {}
+        ```
+        ````
+
+            # indented code is left alone
         ");
 
     const CODE_BLOCKS_CLEAN: &str = indoc!("
@@ -115,24 +180,64 @@ mod tests {
         $ lol
         ```
 
-        ```rust
+        ```{.rust .ignore}
         let x = true;
         ```
 
-        ```rust
+        ```{.rust .no_extras}
         let x = true;
         ```
 
         ```rust
         let x = true;
         ```
+
+        ~~~rust
+        let y = 1;
+        ~~~
+
+        ````rust
+        ```
+        ```
+        ````
+
+            # indented code is left alone
         ");
 
     #[test]
     fn code_block_starts() {
+        let mut document = Document::parse(CODE_BLOCKS);
+        normalize_code_blocks(&mut document, HiddenLines::Remove);
+        assert_eq!(CODE_BLOCKS_CLEAN, document.to_string());
+    }
+
+    #[test]
+    fn kept_hidden_lines() {
+        let mut document = Document::parse(indoc!("
+            ```rust,should_panic
+            # fn main() {
+            panic!();
+            # }
+            ```
+
+            ```rust
+            let x = true;
+            ```
+            "));
+        normalize_code_blocks(&mut document, HiddenLines::Toggle);
         assert_eq!(
-            CODE_BLOCKS_CLEAN,
-            CODE_BLOCKS.normalize()
+            indoc!("
+                ```{.rust .should_panic .hidden-toggle}
+                # fn main() {
+                panic!();
+                # }
+                ```
+
+                ```rust
+                let x = true;
+                ```
+                "),
+            document.to_string()
         );
     }
 }
@@ -1,81 +1,19 @@
 use std::borrow::Cow;
-use regex::Captures;
-use super::patterns::{CODE_BLOCK_TOGGLE, reg};
-
-lazy_static_regex!(REF_LINK, reg::reference::LINK);
-lazy_static_regex!(FOOTNOTE, reg::reference::FOOTNOTE);
-
-fn replace_ref_link<'t>(line: &'t str, filename: &str) -> Cow<'t, str> {
-    let reference_replacer = |capture: &Captures| {
-        let link_id = capture.name("id").expect("No id in ref link").as_str();
-        format!("][{}--{}]", filename, link_id)
-    };
-
-    REF_LINK.replace_all(line, reference_replacer)
-}
-
-fn replace_footnote<'t>(line: &'t str, filename: &str) -> Cow<'t, str> {
-    let footnote_replacer = |capture: &Captures| {
-        let link_id = capture.name("id").expect("No id in footnote").as_str();
-        format!("[^{}--{}]", filename, link_id)
-    };
-
-    FOOTNOTE.replace_all(line, footnote_replacer)
-}
-
-fn replace_ref_url<'t>(line: &'t str, filename: &str) -> Cow<'t, str> {
-    lazy_static_regex!(URL, reg::reference::URL);
-
-    let url_replacer = |capture: &Captures| {
-        let footnote = capture.name("footnote").map(|s| s.as_str()).unwrap_or("");
-        let id       = capture.name("id"  ).expect("No id in ref def"  ).as_str();
-        let link     = capture.name("link").expect("No link in ref def").as_str();
-
-        format!("[{}{}--{}]: {}", footnote, filename, id, link)
-    };
-
-    URL.replace_all(line, url_replacer)
-}
-
-fn replace_all<'t>(
-    line: &'t str,
-    in_code_block: bool,
-    prefix: &str
-) -> Cow<'t, str> {
-    if in_code_block && !line.starts_with(CODE_BLOCK_TOGGLE) {
-        return Cow::from(line);
-    }
-    
-    // TODO: Streamline?
-    match line {
-        l if REF_LINK.is_match(l) => replace_ref_link(line, prefix),
-        l if FOOTNOTE.is_match(l) => replace_footnote(line, prefix),
-        l => replace_ref_url(l, prefix),
-    }
-}
-
-pub trait MdRefsExt: AsRef<str> + Sized{
-    fn prefix_refs_with(self, prefix: &str) -> String {
-        use super::str_iter::UnlineExt;
-        self
-            .as_ref()
-            .lines()
-            .scan(
-                false,
-                |in_code_block, line| {
-                    let new_line = replace_all(line, *in_code_block, prefix);
-                    if line.starts_with(CODE_BLOCK_TOGGLE) {
-                        *in_code_block = !*in_code_block;
-                    }
-                    Some(new_line)
-                }
-            )
-            .unlines_hinted(self.as_ref().len())
+use super::super::markdown::{Document, Node};
+
+/// Prefixes the labels of reference links and footnotes, so chapters
+/// can use the same ones in the aggregated book
+pub fn prefix_refs(document: &mut Document, prefix: &str) {
+    for node in document.nodes_mut() {
+        match node {
+            Node::Label { name, .. } | Node::Footnote { name, .. } => {
+                *name = Cow::Owned(format!("{}--{}", prefix, name));
+            }
+            _ => {}
+        }
     }
 }
 
-impl<S> MdRefsExt for S where S: AsRef<str> {}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -87,21 +25,36 @@ mod tests {
         Odio provident repellendus temporibus possimus magnam odit \
         [neque obcaecati][illo], ab tenetur deserunt quae quia? \
         Asperiores a hic, maiores quaerat, autem ea!
+
+        [amet]: http://amet
+        [elit]: http://elit
+        [illo]: http://illo
         ");
     const WITH_PREFIXED_REFERENCES: &str = indoc!("
         Lorem ipsum [dolor sit][PREFIX--amet], \
         [consectetur adipisicing][PREFIX--elit].
-        
+
         Odio provident repellendus temporibus possimus magnam odit \
         [neque obcaecati][PREFIX--illo], ab tenetur deserunt quae quia? \
         Asperiores a hic, maiores quaerat, autem ea!
+
+        [PREFIX--amet]: http://amet
+        [PREFIX--elit]: http://elit
+        [PREFIX--illo]: http://illo
         ");
 
+    fn prefixed(markdown: &str) -> String {
+        let mut document = Document::parse(markdown);
+        prefix_refs(&mut document, REFERENCE_PREFIX);
+        document.to_string()
+    }
+
     #[test]
     fn reference_renaming() {
+        assert_eq!(WITH_PREFIXED_REFERENCES, prefixed(WITH_REFERENCES));
         assert_eq!(
-            WITH_PREFIXED_REFERENCES.to_string(),
-            WITH_REFERENCES.prefix_refs_with(REFERENCE_PREFIX)
+            "See [x][PREFIX--a] and[^PREFIX--1].\n\n[PREFIX--a]: http://a\n[^PREFIX--1]: Note\n",
+            prefixed("See [x][a] and[^1].\n\n[a]: http://a\n[^1]: Note\n")
         );
     }
 }
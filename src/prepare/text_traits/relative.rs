@@ -0,0 +1,98 @@
+//! Links and images are written relative to the chapter's own file,
+//! in the aggregated book they have to be relative to the source directory.
+
+use std::path::Path;
+use super::super::{markdown::Document, paths};
+
+/// `target` as seen from the source directory, `None` if it is no local file of the book
+fn resolve_target<F>(target: &str, chapter_dir: &str, source_dir: &Path, exists: &F) -> Option<String>
+where
+    F: Fn(&str) -> bool,
+{
+    if target.contains("://") || target.starts_with('#') || target.starts_with('/') || target.starts_with("mailto:") {
+        return None;
+    }
+
+    let (path, fragment) = match target.find('#') {
+        Some(hash) => target.split_at(hash),
+        None => (target, ""),
+    };
+    let resolved = paths::join(chapter_dir, path)?;
+
+    if resolved.ends_with(".md") || resolved.ends_with(".html") {
+        // Chapters keep their link form, normalizing turns them into section ids
+        let source_file = match resolved.rfind(".html") {
+            Some(extension) => format!("{}.md", &resolved[..extension]),
+            None => resolved.clone(),
+        };
+        if exists(&source_file) { Some(resolved + fragment) } else { None }
+    } else if exists(&resolved) {
+        // Pandoc looks up images relative to the working directory
+        Some(format!("{}{}", source_dir.join(&resolved).display(), fragment))
+    } else {
+        None
+    }
+}
+
+fn resolve_paths<F>(document: &mut Document, chapter_dir: &str, source_dir: &Path, exists: &F)
+where
+    F: Fn(&str) -> bool,
+{
+    document.map_destinations(|target| {
+        resolve_target(target, chapter_dir, source_dir, exists).unwrap_or_else(|| target.to_string())
+    });
+}
+
+/// Resolves the links of a chapter in `chapter_dir`, relative to `source_dir`
+pub fn resolve_relative_paths(document: &mut Document, chapter_dir: &str, source_dir: &Path) {
+    let exists = |path: &str| source_dir.join(path).is_file();
+    resolve_paths(document, chapter_dir, source_dir, &exists);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILES: &[&str] = &["ch03/ownership.md", "ch03/img/stack.svg", "ch04/structs.md", "README.md"];
+
+    const CHAPTER: &str = indoc!("
+        See [structs](../ch04/structs.md#methods), [the intro](../README.html)
+        and [moves](ownership.md).
+
+        ![The stack](img/stack.svg \"Stack\")
+
+        [`Vec`](../../std/vec/struct.Vec.html) is [elsewhere](https://doc.rust-lang.org/)
+
+        ```rust
+        let x = a[i](../ch04/structs.md);
+        ```
+
+        [struct]: ../ch04/structs.md
+        [^note]: ownership.md
+        ");
+
+    const RESOLVED: &str = indoc!("
+        See [structs](ch04/structs.md#methods), [the intro](README.html)
+        and [moves](ch03/ownership.md).
+
+        ![The stack](book/ch03/img/stack.svg \"Stack\")
+
+        [`Vec`](../../std/vec/struct.Vec.html) is [elsewhere](https://doc.rust-lang.org/)
+
+        ```rust
+        let x = a[i](../ch04/structs.md);
+        ```
+
+        [struct]: ch04/structs.md
+        [^note]: ownership.md
+        ");
+
+    #[test]
+    fn relative_paths() {
+        let exists = |path: &str| FILES.contains(&path);
+        let mut document = Document::parse(CHAPTER);
+        resolve_paths(&mut document, "ch03", Path::new("book"), &exists);
+        assert_eq!(RESOLVED, document.to_string());
+        assert_eq!(None, resolve_target("../std/index.html", "", Path::new("book"), &exists));
+    }
+}
@@ -1,107 +1,426 @@
+//! `SUMMARY.md` in mdBook's grammar: an optional title, prefix chapters,
+//! numbered chapters in (nested) lists that may be grouped by part titles,
+//! suffix chapters, and separators. Draft chapters have an empty link.
+//! A link to another book's `SUMMARY.md` includes that book as a part.
+
 use std::{
-    ops::Div,
+    fmt::{self, Display, Formatter},
     path::Path,
+    slice,
     str::FromStr,
 };
 use failure::Error;
+use pulldown_cmark::{Event, Parser, Tag};
+use ::aux::file;
+use super::paths;
+
+const SUMMARY: &str = "SUMMARY.md";
+pub const INTRODUCTION: &str = "README.md";
 
-const TOC_PATTERN: &str =
-    r"(?x)(?P<indent>\s*?)\*\s\[(?P<title>.+?)\]\((?P<filename>.+?)\)";
+/// Deeper includes are most likely a cycle
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ChapterKind {
+    /// Unnumbered, before the first list
+    Prefix,
+    /// In a list
+    Numbered,
+    /// Unnumbered, after the last list
+    Suffix,
+}
 
 #[derive(Debug, PartialEq)]
 pub struct Chapter {
-    pub filename: String,
+    pub title: String,
+    /// Relative to the source directory, `None` for draft chapters which have no file yet
+    pub filename: Option<String>,
     pub header: String,
     pub nest_level: usize,
+    pub kind: ChapterKind,
+}
+
+impl Chapter {
+    pub fn is_draft(&self) -> bool { self.filename.is_none() }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Entry {
+    /// A `# Part Title` grouping the numbered chapters after it
+    Part(String),
+    /// A `---` rule
+    Separator,
+    Chapter(Chapter),
+    /// A link to another book's `SUMMARY.md`, replaced by that book on loading
+    Include { title: String, path: String },
 }
 
 fn build_header(title: &str, filename: &str) -> Result<String, Error> {
-    let section_slug = Path::new(&filename)
+    Path::new(&filename)
         .file_stem()
         .ok_or_else(|| format_err!(
             "No ToC filename for chapter '{}'",
             title
-        ))?
-        .to_str()
-        .ok_or_else(|| format_err!(
-            "Illegal characters in filename for chapter '{}'",
-            title
         ))?;
-      
-    Ok(format!("{} {{#sec--{}}}", title, section_slug))
+
+    Ok(format!("{} {{#{}}}", title, paths::section_id(filename)))
 }
 
-impl FromStr for Chapter {
+fn line_at(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+/// Reads the inline markdown of a header or link title, up to the end of its tag
+fn inline_markdown<'a, I>(events: &mut I) -> Result<String, String>
+where
+    I: Iterator<Item = Event<'a>>
+{
+    let mut markdown = String::new();
+    for event in events {
+        match event {
+            Event::End(Tag::Header(_)) | Event::End(Tag::Link(..)) => return Ok(markdown.trim().to_string()),
+            Event::Text(text) => markdown.push_str(&text),
+            Event::SoftBreak => markdown.push(' '),
+            Event::Start(Tag::Code) | Event::End(Tag::Code) => markdown.push('`'),
+            Event::Start(Tag::Emphasis) | Event::End(Tag::Emphasis) => markdown.push('*'),
+            Event::Start(Tag::Strong) | Event::End(Tag::Strong) => markdown.push_str("**"),
+            other => return Err(format!("unexpected {:?} in title", other)),
+        }
+    }
+    Err("unterminated title".into())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TableOfContents {
+    pub title: Option<String>,
+    pub entries: Vec<Entry>,
+}
+
+impl FromStr for TableOfContents {
     type Err = Error;
 
     fn from_str(source: &str) -> Result<Self, Self::Err> {
-        lazy_static_regex!(TOC, TOC_PATTERN);
+        let mut parser = Parser::new(source);
+        let mut title = None;
+        let mut entries = Vec::new();
+
+        let mut list_depth: usize = 0;
+        let mut seen_list = false;
+        // The line of a list item that has not got its link yet
+        let mut pending_item: Option<usize> = None;
+
+        while let Some(event) = parser.next() {
+            let line = line_at(source, parser.get_offset());
+            let fail = |message: String| format_err!("SUMMARY.md line {}: {}", line, message);
 
-        let capture = TOC
-            .captures(source)
-            .ok_or_else(|| format_err!("Failed extracting ToC chapter '{}'", source))?;
-        
-        let filename = capture
-            .name("filename").unwrap().as_str()
-            .to_string();
-        
-        let nest_level = capture
-            .name("indent").unwrap().as_str()
-            .chars().count().div(4);
+            match event {
+                Event::Start(Tag::Header(level)) => {
+                    let text = inline_markdown(&mut parser).map_err(&fail)?;
+                    if level == 1 && title.is_none() && entries.is_empty() {
+                        title = Some(text);
+                    } else {
+                        entries.push(Entry::Part(text));
+                    }
+                }
+                Event::Start(Tag::Rule) => entries.push(Entry::Separator),
+                Event::End(Tag::Rule) => {},
 
-        let header = build_header(
-            capture.name("title").unwrap().as_str(),
-            &filename,
-        )?;
+                Event::Start(Tag::List(_)) => {
+                    if let Some(line) = pending_item {
+                        bail!("SUMMARY.md line {}: list item without a chapter link", line);
+                    }
+                    let has_suffix = entries.iter().any(|entry| match entry {
+                        Entry::Chapter(chapter) => chapter.kind == ChapterKind::Suffix,
+                        _ => false,
+                    });
+                    if list_depth == 0 && has_suffix {
+                        return Err(fail("numbered chapters can not follow suffix chapters".into()));
+                    }
+                    list_depth += 1;
+                    seen_list = true;
+                }
+                Event::End(Tag::List(_)) => list_depth -= 1,
+                Event::Start(Tag::Item) => pending_item = Some(line),
+                Event::End(Tag::Item) => if let Some(line) = pending_item.take() {
+                    bail!("SUMMARY.md line {}: list item without a chapter link", line);
+                },
+                Event::Start(Tag::Paragraph) | Event::End(Tag::Paragraph) => {},
 
-        Ok(Chapter { filename, header, nest_level })
+                Event::Start(Tag::Link(destination, _)) => {
+                    let kind = match (list_depth, seen_list) {
+                        (0, false) => ChapterKind::Prefix,
+                        (0, true) => ChapterKind::Suffix,
+                        _ => ChapterKind::Numbered,
+                    };
+                    if kind == ChapterKind::Numbered && pending_item.take().is_none() {
+                        return Err(fail("only one chapter link per list item".into()));
+                    }
+
+                    let title = inline_markdown(&mut parser).map_err(&fail)?;
+
+                    if destination.ends_with(SUMMARY) {
+                        if list_depth > 1 {
+                            return Err(fail("included books must not be nested in chapters".into()));
+                        }
+                        entries.push(Entry::Include { title, path: destination.to_string() });
+                        continue;
+                    }
+
+                    let filename = if destination.is_empty() { None } else { Some(destination.to_string()) };
+                    let header = match filename {
+                        Some(ref filename) => build_header(&title, filename).map_err(|e| fail(e.to_string()))?,
+                        None => title.clone(),
+                    };
+
+                    entries.push(Entry::Chapter(Chapter {
+                        title,
+                        filename,
+                        header,
+                        nest_level: list_depth.saturating_sub(1),
+                        kind,
+                    }));
+                }
+
+                Event::Text(ref text) if text.trim().is_empty() => {},
+                Event::SoftBreak | Event::HardBreak => {},
+                Event::Text(text) => return Err(fail(format!("expected a chapter link, found '{}'", text))),
+                other => return Err(fail(format!("expected a chapter link, found {:?}", other))),
+            }
+        }
+
+        Ok(TableOfContents { title, entries })
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct TableOfContents(Vec<Chapter>);
+impl TableOfContents {
+    /// Reads `SUMMARY.md` from `source` and includes the books it links to
+    pub fn load(source: &Path) -> Result<Self, Error> {
+        TableOfContents::load_book(source, "", 0)
+    }
 
-impl FromStr for TableOfContents {
-    type Err = Error;
+    fn load_book(source: &Path, book_dir: &str, depth: usize) -> Result<Self, Error> {
+        if depth > MAX_INCLUDE_DEPTH {
+            bail!("Books included more than {} levels deep, is there a cycle?", MAX_INCLUDE_DEPTH);
+        }
 
-    fn from_str(source: &str) -> Result<Self, Self::Err> {
-        source
-            .lines()
-            .filter(|line| line.trim_left().starts_with('*'))
-            .map(Chapter::from_str)
-            .collect::<Result<Vec<Chapter>, Error>>()
-            .map(TableOfContents)
+        let summary = source.join(book_dir).join(SUMMARY);
+        let toc = file::to_string(&summary)
+            .map_err(|e| format_err!("Failed to read {}: {}", summary.display(), e))?
+            .parse::<TableOfContents>()
+            .map_err(|e| format_err!("{} ({})", e, summary.display()))?;
+
+        let mut entries = Vec::with_capacity(toc.entries.len());
+        for entry in toc.entries {
+            match entry {
+                Entry::Chapter(mut chapter) => {
+                    if let Some(filename) = chapter.filename.take() {
+                        let path = paths::join(book_dir, &filename).ok_or_else(|| format_err!(
+                            "Chapter {} is outside of {}", filename, source.display()
+                        ))?;
+                        chapter.header = build_header(&chapter.title, &path)?;
+                        chapter.filename = Some(path);
+                    }
+                    entries.push(Entry::Chapter(chapter));
+                }
+                Entry::Include { title, path } => {
+                    let included_dir = paths::join(book_dir, paths::parent(&path)).ok_or_else(|| format_err!(
+                        "Included book {} is outside of {}", path, source.display()
+                    ))?;
+
+                    entries.push(Entry::Part(title));
+                    if source.join(&included_dir).join(INTRODUCTION).is_file() {
+                        let filename = paths::join(&included_dir, INTRODUCTION).unwrap_or_default();
+                        entries.push(Entry::Chapter(Chapter {
+                            title: "Introduction".into(),
+                            header: build_header("Introduction", &filename)?,
+                            filename: Some(filename),
+                            nest_level: 0,
+                            kind: ChapterKind::Prefix,
+                        }));
+                    }
+                    entries.extend(TableOfContents::load_book(source, &included_dir, depth + 1)?.entries);
+                }
+                other => entries.push(other),
+            }
+        }
+
+        Ok(TableOfContents { title: toc.title, entries })
+    }
+}
+
+/// The position of a numbered chapter, like `3.2.1`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionNumber(pub Vec<usize>);
+
+impl Display for SectionNumber {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(|part| part.to_string()).collect();
+        write!(fmt, "{}", parts.join("."))
     }
 }
 
-impl IntoIterator for TableOfContents {
-    type IntoIter = <Vec<Chapter> as IntoIterator>::IntoIter;
-    type Item = Chapter;
+pub type NodeId = usize;
+
+#[derive(Debug)]
+pub struct Node<'a> {
+    pub chapter: &'a Chapter,
+    /// The part title the chapter is listed under
+    pub part: Option<&'a str>,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+    /// Only numbered chapters (drafts included) have one
+    pub number: Option<SectionNumber>,
+}
+
+/// The chapters of a `TableOfContents` as a tree, nodes are stored in document order
+#[derive(Debug)]
+pub struct ChapterTree<'a> {
+    nodes: Vec<Node<'a>>,
+    roots: Vec<NodeId>,
+}
+
+impl<'a> ChapterTree<'a> {
+    pub fn new(toc: &'a TableOfContents) -> Self {
+        let mut tree = ChapterTree { nodes: Vec::new(), roots: Vec::new() };
+        let mut part = None;
+        // The innermost open chapter on each nesting level
+        let mut ancestors: Vec<NodeId> = Vec::new();
 
-    fn into_iter(self) -> Self::IntoIter { self.0.into_iter() }
+        for entry in &toc.entries {
+            let chapter = match entry {
+                Entry::Chapter(chapter) => chapter,
+                Entry::Part(title) => { part = Some(title.as_str()); continue }
+                Entry::Separator | Entry::Include { .. } => continue,
+            };
+
+            let id = tree.nodes.len();
+            ancestors.truncate(chapter.nest_level);
+            let parent = ancestors.last().cloned();
+
+            match parent {
+                Some(parent) => tree.nodes[parent].children.push(id),
+                None => tree.roots.push(id),
+            }
+            tree.nodes.push(Node {
+                chapter,
+                // Suffix chapters close the last part, prefix chapters only
+                // follow a part at the start of an included book
+                part: if chapter.kind == ChapterKind::Suffix { None } else { part },
+                parent,
+                children: Vec::new(),
+                number: None,
+            });
+            ancestors.push(id);
+        }
+
+        // A number is the parent's number followed by the position among the
+        // numbered siblings, parents are numbered first in document order
+        for id in 0..tree.nodes.len() {
+            if tree.nodes[id].chapter.kind != ChapterKind::Numbered { continue }
+
+            let position = tree.siblings(id)
+                .iter()
+                .filter(|&&sibling| tree.nodes[sibling].chapter.kind == ChapterKind::Numbered)
+                .take_while(|&&sibling| sibling != id)
+                .count();
+            let mut number = tree.nodes[id].parent
+                .and_then(|parent| tree.nodes[parent].number.clone())
+                .map(|number| number.0)
+                .unwrap_or_default();
+            number.push(position + 1);
+            tree.nodes[id].number = Some(SectionNumber(number));
+        }
+
+        tree
+    }
+
+    pub fn get(&self, id: NodeId) -> &Node<'a> { &self.nodes[id] }
+
+    /// All chapters in document order
+    pub fn iter(&self) -> slice::Iter<'_, Node<'a>> { self.nodes.iter() }
+
+    pub fn roots(&self) -> &[NodeId] { &self.roots }
+
+    /// The chapters sharing a parent with `id`, `id` included
+    pub fn siblings(&self, id: NodeId) -> &[NodeId] {
+        match self.nodes[id].parent {
+            Some(parent) => &self.nodes[parent].children,
+            None => &self.roots,
+        }
+    }
+
+    /// `id` and everything below it, depth first
+    pub fn depth_first(&self, id: NodeId) -> Vec<NodeId> {
+        let mut order = Vec::new();
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            order.push(id);
+            stack.extend(self.nodes[id].children.iter().rev());
+        }
+        order
+    }
+
+    pub fn find(&self, filename: &str) -> Option<NodeId> {
+        self.nodes
+            .iter()
+            .position(|node| node.chapter.filename.as_ref().map(String::as_str) == Some(filename))
+    }
+}
+
+impl FromStr for Chapter {
+    type Err = Error;
+
+    /// A single chapter entry, like `* [Title](file.md)`
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        source
+            .parse::<TableOfContents>()?
+            .entries
+            .into_iter()
+            .filter_map(|entry| match entry {
+                Entry::Chapter(chapter) => Some(chapter),
+                _ => None,
+            })
+            .next()
+            .ok_or_else(|| format_err!("Failed extracting ToC chapter '{}'", source))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    fn numbered(title: &str, filename: &str, nest_level: usize) -> Entry {
+        Entry::Chapter(Chapter {
+            title: title.into(),
+            filename: Some(filename.into()),
+            header: build_header(title, filename).unwrap(),
+            nest_level,
+            kind: ChapterKind::Numbered,
+        })
+    }
+
     #[test]
     fn chapter_from_str() {
         assert_eq!(
             Chapter {
-                filename: "guessing-game.md".into(),
+                title: "Tutorial: Guessing Game".into(),
+                filename: Some("guessing-game.md".into()),
                 header: "Tutorial: Guessing Game {#sec--guessing-game}".into(),
-                nest_level: 0
+                nest_level: 0,
+                kind: ChapterKind::Numbered,
             },
             "* [Tutorial: Guessing Game](guessing-game.md)".parse().unwrap()
         );
 
         assert_eq!(
             Chapter {
-                filename: "variable-bindings.md".into(),
-                header: "Variable Bindings {#sec--variable-bindings}".into(),
-                nest_level: 1
+                title: "`const` and `static`".into(),
+                filename: Some("const-and-static.md".into()),
+                header: "`const` and `static` {#sec--const-and-static}".into(),
+                nest_level: 0,
+                kind: ChapterKind::Numbered,
             },
-            "    * [Variable Bindings](variable-bindings.md)".parse().unwrap()
+            "* [`const` and `static`](const-and-static.md)".parse().unwrap()
         );
     }
 
@@ -112,48 +431,158 @@ mod tests {
         * [Syntax and Semantics](syntax-and-semantics.md)
             * [Functions](functions.md)
         * [Effective Rust](effective-rust.md)
-            * [The Stack and the Heap](the-stack-and-the-heap.md)
+        \t* [The Stack and the Heap](the-stack-and-the-heap.md)
             * [Testing](testing.md)
     ");
 
     #[test]
     fn toc_from_str() {
         let correct_toc = vec![
-            Chapter {
-                filename: "guessing-game.md".into(),
-                header: "Tutorial: Guessing Game {#sec--guessing-game}".into(),
-                nest_level: 0,
-            },
-            Chapter {
-                filename: "syntax-and-semantics.md".into(),
-                header: "Syntax and Semantics {#sec--syntax-and-semantics}".into(),
-                nest_level: 0,
-            },
-            Chapter {
-                filename: "functions.md".into(),
-                header: "Functions {#sec--functions}".into(),
-                nest_level: 1,
-            },
-            Chapter {
-                filename: "effective-rust.md".into(),
-                header: "Effective Rust {#sec--effective-rust}".into(),
-                nest_level: 0,
-            },
-            Chapter {
-                filename: "the-stack-and-the-heap.md".into(),
-                header: "The Stack and the Heap {#sec--the-stack-and-the-heap}".into(),
-                nest_level: 1,
-            },
-            Chapter {
-                filename: "testing.md".into(),
-                header: "Testing {#sec--testing}".into(),
-                nest_level: 1,
-            },
+            numbered("Tutorial: Guessing Game", "guessing-game.md", 0),
+            numbered("Syntax and Semantics", "syntax-and-semantics.md", 0),
+            numbered("Functions", "functions.md", 1),
+            numbered("Effective Rust", "effective-rust.md", 0),
+            numbered("The Stack and the Heap", "the-stack-and-the-heap.md", 1),
+            numbered("Testing", "testing.md", 1),
         ];
 
+        let toc = TOC_TEXT.parse::<TableOfContents>().unwrap();
+        assert_eq!(Some("Sample TOC".to_string()), toc.title);
+        assert_eq!(correct_toc, toc.entries);
+    }
+
+    #[test]
+    fn mdbook_grammar() {
+        let toc = indoc!("
+            # Summary
+
+            [Foreword](foreword.md)
+
+            # Part I
+
+            - [Getting Started](start.md)
+              - [Installation](install.md)
+              - [Drafted]()
+
+            ---
+
+            # Part II
+
+            1. [Ownership](ownership.md)
+
+            [Appendix](appendix.md)
+            [The Rustonomicon](nomicon/SUMMARY.md)
+            ").parse::<TableOfContents>().unwrap();
+
+        let kinds: Vec<String> = toc.entries.iter().map(|entry| match entry {
+            Entry::Part(title) => format!("part {}", title),
+            Entry::Separator => "separator".into(),
+            Entry::Include { title, path } => format!("include {} {}", title, path),
+            Entry::Chapter(chapter) => format!(
+                "{:?} {} {} {}",
+                chapter.kind,
+                chapter.nest_level,
+                chapter.title,
+                if chapter.is_draft() { "draft" } else { "" },
+            ).trim().to_string(),
+        }).collect();
+
+        assert_eq!(
+            vec![
+                "Prefix 0 Foreword",
+                "part Part I",
+                "Numbered 0 Getting Started",
+                "Numbered 1 Installation",
+                "Numbered 1 Drafted draft",
+                "separator",
+                "part Part II",
+                "Numbered 0 Ownership",
+                "Suffix 0 Appendix",
+                "include The Rustonomicon nomicon/SUMMARY.md",
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn chapter_tree() {
+        let toc = indoc!("
+            [Foreword](foreword.md)
+
+            # Part I
+
+            - [Getting Started](start.md)
+              - [Installation](install.md)
+                - [Windows](windows.md)
+              - [Hello](hello.md)
+            - [Ownership](ownership.md)
+            ").parse::<TableOfContents>().unwrap();
+        let tree = ChapterTree::new(&toc);
+
+        let numbers: Vec<String> = tree.iter()
+            .map(|node| node.number.as_ref().map(ToString::to_string).unwrap_or_default())
+            .collect();
+        assert_eq!(vec!["", "1", "1.1", "1.1.1", "1.2", "2"], numbers);
+
+        assert_eq!(&[0, 1, 5], tree.roots());
+        assert_eq!(&[2, 4], tree.get(1).children.as_slice());
+        assert_eq!(&[3], tree.get(2).children.as_slice());
+        assert_eq!(None, tree.get(0).part);
+
+        let install = tree.find("install.md").unwrap();
+        let start = tree.find("start.md").unwrap();
+        assert_eq!(Some(start), tree.get(install).parent);
+        assert_eq!(&[install, tree.find("hello.md").unwrap()], tree.siblings(install));
+        assert_eq!(Some("Part I"), tree.get(start).part);
+        assert_eq!(vec![start, install, install + 1, install + 2], tree.depth_first(start));
+        assert_eq!(3, tree.roots().len());
+        assert_eq!(None, tree.find("missing.md"));
+    }
+
+    #[test]
+    fn malformed_entries() {
+        let error = "# Summary\n\n* [A](a.md)\n* Just text\n".parse::<TableOfContents>().unwrap_err();
+        assert_eq!("SUMMARY.md line 4: expected a chapter link, found 'Just text'", error.to_string());
+
+        let error = "* [A](a.md)\n\n[B](b.md)\n\n* [C](c.md)\n".parse::<TableOfContents>().unwrap_err();
+        assert_eq!("SUMMARY.md line 5: numbered chapters can not follow suffix chapters", error.to_string());
+
+        let error = "* [A](a.md)\n    * [B](b/SUMMARY.md)\n".parse::<TableOfContents>().unwrap_err();
+        assert_eq!("SUMMARY.md line 2: included books must not be nested in chapters", error.to_string());
+    }
+
+    #[test]
+    fn included_books() {
+        use std::{env, fs};
+
+        let source = env::temp_dir().join(format!("compile-trpl-toc-{}", ::std::process::id()));
+        let write = |path: &str, text: &str| {
+            let path = source.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        };
+        write("SUMMARY.md", "# Omnibus\n\n- [Ownership](ch03/ownership.md)\n- [The Rustonomicon](nomicon/SUMMARY.md)\n");
+        write("nomicon/SUMMARY.md", "# Summary\n\n- [Data](data.md)\n    - [Repr](../nomicon/repr.md)\n");
+        write("nomicon/README.md", "% The Rustonomicon\n");
+
+        let toc = TableOfContents::load(&source);
+        fs::remove_dir_all(&source).unwrap();
+        let toc = toc.unwrap();
+
+        let chapters: Vec<(&str, &str)> = ChapterTree::new(&toc)
+            .iter()
+            .map(|node| (node.chapter.filename.as_ref().unwrap().as_str(), node.chapter.header.as_str()))
+            .collect();
         assert_eq!(
-            correct_toc,
-            TOC_TEXT.parse::<TableOfContents>().unwrap().0
+            vec![
+                ("ch03/ownership.md", "Ownership {#sec--ch03-ownership}"),
+                ("nomicon/README.md", "Introduction {#sec--nomicon-README}"),
+                ("nomicon/data.md", "Data {#sec--nomicon-data}"),
+                ("nomicon/repr.md", "Repr {#sec--nomicon-repr}"),
+            ],
+            chapters
         );
+        let parts: Vec<Option<&str>> = ChapterTree::new(&toc).iter().map(|node| node.part).collect();
+        assert_eq!(vec![None, Some("The Rustonomicon"), Some("The Rustonomicon"), Some("The Rustonomicon")], parts);
     }
 }
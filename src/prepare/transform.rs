@@ -0,0 +1,255 @@
+//! The passes a chapter goes through on its way into the book. Books choose
+//! theirs by name in `books.toml`, in order; passes of their own are external
+//! programs declared as `[[transform]]`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::Path,
+    process::{self, Stdio},
+    thread,
+};
+use failure::{Error, ResultExt};
+use super::{
+    markdown::Document,
+    paths,
+    text_traits::{
+        adjust::{increase_title_level, remove_file_title},
+        normalize::{self, normalize_code_blocks},
+        references::prefix_refs,
+    },
+};
+
+/// The built-in passes every book goes through unless it lists its own
+pub const DEFAULT: &[&str] = &["title", "links", "headings", "references", "normalize", "code-blocks"];
+
+/// What happens to the hidden `# ` lines of Rust examples
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HiddenLines {
+    /// Dropped, like the online book does
+    Remove,
+    /// Printed greyed out
+    Grey,
+    /// Collapsed behind a toggle in HTML, greyed out elsewhere
+    Toggle,
+}
+
+impl Default for HiddenLines {
+    fn default() -> Self { HiddenLines::Remove }
+}
+
+/// What a pass knows about the chapter it transforms
+pub struct Chapter<'a> {
+    /// Path of the chapter file in the book's source directory
+    pub filename: &'a str,
+    pub path_prefix: &'a Path,
+    /// Level the chapter's top level headings end up at
+    pub title_bump: usize,
+    /// Prepended to the chapter's reference and footnote ids
+    pub ref_prefix: &'a str,
+    /// Ids of the chapter's own headings, before the `headings` pass qualifies them
+    pub headings: HashSet<String>,
+    pub doc_urls: &'a HashMap<String, String>,
+    pub hidden_lines: HiddenLines,
+}
+
+pub trait Transform {
+    /// The name books list the pass by
+    fn name(&self) -> &str;
+
+    fn apply(&self, document: &mut Document, chapter: &Chapter) -> Result<(), Error>;
+}
+
+/// A pass of the crate itself
+struct Builtin {
+    name: &'static str,
+    apply: fn(&mut Document, &Chapter),
+}
+
+impl Transform for Builtin {
+    fn name(&self) -> &str { self.name }
+
+    fn apply(&self, document: &mut Document, chapter: &Chapter) -> Result<(), Error> {
+        (self.apply)(document, chapter);
+        Ok(())
+    }
+}
+
+fn title(document: &mut Document, _chapter: &Chapter) {
+    remove_file_title(document);
+}
+
+fn links(document: &mut Document, chapter: &Chapter) {
+    super::prepare_links(document, chapter.filename, chapter.path_prefix, &chapter.headings);
+}
+
+fn headings(document: &mut Document, chapter: &Chapter) {
+    increase_title_level(document, chapter.title_bump, &paths::section_id(chapter.filename));
+}
+
+fn references(document: &mut Document, chapter: &Chapter) {
+    prefix_refs(document, chapter.ref_prefix);
+}
+
+fn normalize(document: &mut Document, chapter: &Chapter) {
+    normalize::normalize(document, chapter.doc_urls);
+}
+
+fn code_blocks(document: &mut Document, chapter: &Chapter) {
+    normalize_code_blocks(document, chapter.hidden_lines);
+}
+
+/// A pass run by an external program: it reads the chapter's markdown
+/// from stdin and writes the transformed markdown to stdout, the chapter
+/// file is in `CHAPTER_FILE`
+pub struct External {
+    pub name: String,
+    /// Program and arguments
+    pub command: Vec<String>,
+}
+
+impl Transform for External {
+    fn name(&self) -> &str { &self.name }
+
+    fn apply(&self, document: &mut Document, chapter: &Chapter) -> Result<(), Error> {
+        let (program, arguments) = self.command
+            .split_first()
+            .ok_or_else(|| format_err!("No command given"))?;
+
+        let mut child = process::Command::new(program)
+            .args(arguments)
+            .env("CHAPTER_FILE", chapter.path_prefix.join(chapter.filename))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context(format!("Failed to execute {}", program))?;
+
+        // Written from another thread, the program may answer before it read everything
+        let mut stdin = child.stdin.take().ok_or_else(|| format_err!("Failed to get {} stdin", program))?;
+        let input = document.to_string();
+        let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+        let output = child.wait_with_output()?;
+        writer.join().map_err(|_| format_err!("Failed to write to {}", program))??;
+        if !output.status.success() {
+            bail!("{} exited unsuccessfully", program);
+        }
+
+        let markdown = String::from_utf8(output.stdout)
+            .map_err(|e| format_err!("{} wrote invalid UTF-8: {}", program, e))?;
+        *document = Document::parse(&markdown).into_owned();
+        Ok(())
+    }
+}
+
+/// The passes books can choose from
+pub struct Registry {
+    transforms: Vec<Box<dyn Transform>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let builtin = |name: &'static str, apply: fn(&mut Document, &Chapter)| Box::new(Builtin { name, apply }) as Box<dyn Transform>;
+
+        Registry {
+            transforms: vec![
+                builtin("title", title),
+                builtin("links", links),
+                builtin("headings", headings),
+                builtin("references", references),
+                builtin("normalize", normalize),
+                builtin("code-blocks", code_blocks),
+            ],
+        }
+    }
+}
+
+impl Registry {
+    pub fn register(&mut self, transform: Box<dyn Transform>) -> Result<(), Error> {
+        if self.get(transform.name()).is_some() {
+            bail!("Transform '{}' is defined more than once", transform.name());
+        }
+        self.transforms.push(transform);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Transform> {
+        self.transforms
+            .iter()
+            .find(|transform| transform.name() == name)
+            .map(|transform| transform.as_ref())
+    }
+
+    /// The passes called `names` in their order, the `DEFAULT` ones without names.
+    /// `links` points local links at the ids `headings` gives, so a pipeline
+    /// with `links` needs `headings` too.
+    pub fn pipeline<S: AsRef<str>>(&self, names: Option<&[S]>) -> Result<Vec<&dyn Transform>, Error> {
+        let get = |name: &str| self.get(name).ok_or_else(|| format_err!("Unknown transform '{}'", name));
+
+        let pipeline = match names {
+            Some(names) => names.iter().map(|name| get(name.as_ref())).collect::<Result<Vec<_>, _>>()?,
+            None => DEFAULT.iter().map(|name| get(name)).collect::<Result<Vec<_>, _>>()?,
+        };
+
+        let has = |name| pipeline.iter().any(|transform| transform.name() == name);
+        if has("links") && !has("headings") {
+            bail!("Transform 'links' needs 'headings' to give the ids it links to");
+        }
+        Ok(pipeline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(pipeline: &[&dyn Transform]) -> Vec<String> {
+        pipeline.iter().map(|transform| transform.name().to_string()).collect()
+    }
+
+    #[test]
+    fn pipelines() {
+        let mut registry = Registry::default();
+        let glossary = External { name: "glossary".into(), command: vec!["glossary-links".into()] };
+        registry.register(Box::new(glossary)).unwrap();
+
+        assert_eq!(DEFAULT.to_vec(), names(&registry.pipeline::<&str>(None).unwrap()));
+        assert_eq!(
+            vec!["glossary", "links", "headings"],
+            names(&registry.pipeline(Some(&["glossary", "links", "headings"][..])).unwrap())
+        );
+        assert!(registry.pipeline(Some(&["links", "spelling"][..])).is_err());
+        assert!(registry.pipeline(Some(&["headings", "references"][..])).is_ok());
+        assert!(registry.pipeline(Some(&["links", "references"][..])).is_err());
+        assert!(registry.pipeline(Some(&["headings", "links"][..])).is_ok());
+
+        let duplicate = External { name: "links".into(), command: vec!["true".into()] };
+        assert!(registry.register(Box::new(duplicate)).is_err());
+    }
+
+    #[test]
+    fn external_transform() {
+        let doc_urls = HashMap::new();
+        let chapter = Chapter {
+            filename: "ch01.md",
+            path_prefix: Path::new("book"),
+            title_bump: 1,
+            ref_prefix: "ch01.md",
+            headings: HashSet::new(),
+            doc_urls: &doc_urls,
+            hidden_lines: HiddenLines::Remove,
+        };
+        let glossary = External {
+            name: "glossary".into(),
+            command: vec!["sed".into(), "s/crate/[crate](#sec--glossary)/".into()],
+        };
+
+        let mut document = Document::parse("Every crate has a `crate` root.\n");
+        glossary.apply(&mut document, &chapter).unwrap();
+        assert_eq!("Every [crate](#sec--glossary) has a `crate` root.\n", document.to_string());
+
+        let failing = External { name: "failing".into(), command: vec!["false".into()] };
+        assert!(failing.apply(&mut document, &chapter).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use super::{
+    markup::{escape, Renderer},
+    sections::{self, NavPoint, Section},
+    Format,
+    Output,
+};
+use metadata::BookMetadata;
+use prepare::Contents;
+use failure::Error;
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+use zip::{
+    write::FileOptions,
+    CompressionMethod,
+    ZipWriter,
+};
+
+const STYLESHEET: &str = "lib/epub.css";
+const EXTENSION: &str = "xhtml";
+
+mod templates {
+    pub const CONTAINER: &str = indoc!(r#"
+        <?xml version="1.0" encoding="UTF-8"?>
+        <container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+        <rootfiles>
+        <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+        </rootfiles>
+        </container>
+        "#);
+
+    pub const PACKAGE: &str = indoc!(r#"
+        <?xml version="1.0" encoding="UTF-8"?>
+        <package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id" xml:lang="{lang}">
+        <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+        <dc:identifier id="book-id">{identifier}</dc:identifier>
+        <dc:title>{title}</dc:title>
+        {creators}<dc:language>{lang}</dc:language>
+        <dc:date>{date}</dc:date>
+        <dc:description>{description}</dc:description>
+        {metadata}<meta property="dcterms:modified">{date}T00:00:00Z</meta>
+        </metadata>
+        <manifest>
+        <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+        <item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+        <item id="style" href="style.css" media-type="text/css"/>
+        {manifest}</manifest>
+        <spine toc="ncx">
+        <itemref idref="nav"/>
+        {spine}</spine>
+        <guide>
+        <reference type="toc" title="Table of Contents" href="nav.xhtml"/>
+        {guide}</guide>
+        </package>
+        "#);
+
+    pub const NCX: &str = indoc!(r#"
+        <?xml version="1.0" encoding="UTF-8"?>
+        <ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+        <head>
+        <meta name="dtb:uid" content="{identifier}"/>
+        </head>
+        <docTitle><text>{title}</text></docTitle>
+        <navMap>
+        {nav_map}</navMap>
+        </ncx>
+        "#);
+
+    pub const NAV_POINT: &str = indoc!(r#"
+        <navPoint id="nav-{order}" playOrder="{order}">
+        <navLabel><text>{title}</text></navLabel>
+        <content src="{src}"/>
+        "#);
+
+    pub const XHTML: &str = indoc!(r#"
+        <?xml version="1.0" encoding="UTF-8"?>
+        <!DOCTYPE html>
+        <html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" xml:lang="{lang}" lang="{lang}">
+        <head>
+        <meta charset="UTF-8"/>
+        <title>{title}</title>
+        <link rel="stylesheet" type="text/css" href="style.css"/>
+        </head>
+        <body>
+        {body}</body>
+        </html>
+        "#);
+}
+
+fn nav_list(points: &[NavPoint], output: &mut String) {
+    output.push_str("<ol>\n");
+    for point in points {
+        output.push_str(&format!(
+            "<li><a href=\"{}\">{}</a>",
+            point.section.file_name(EXTENSION),
+            escape(&point.section.title),
+        ));
+        if !point.children.is_empty() { nav_list(&point.children, output) }
+        output.push_str("</li>\n");
+    }
+    output.push_str("</ol>\n");
+}
+
+fn ncx_points(points: &[NavPoint], play_order: &mut usize, output: &mut String) {
+    for point in points {
+        *play_order += 1;
+        output.push_str(&templates::NAV_POINT
+            .replace("{order}", &play_order.to_string())
+            .replace("{src}", &point.section.file_name(EXTENSION))
+            .replace("{title}", &escape(&point.section.title))
+        );
+        ncx_points(&point.children, play_order, output);
+        output.push_str("</navPoint>\n");
+    }
+}
+
+fn xhtml_document(title: &str, language: &str, body: &str) -> String {
+    templates::XHTML
+        .replace("{lang}", &escape(language))
+        .replace("{title}", &escape(title))
+        .replace("{body}", body)
+}
+
+/// The unique identifier of the book, the first one from the metadata if there is any
+fn identifier(meta: &BookMetadata, output: &Output) -> String {
+    meta.identifiers
+        .first()
+        .map(|identifier| identifier.text.clone())
+        .unwrap_or_else(|| format!("urn:x-rust-ebook:{}-{}", output.prefix, output.date))
+}
+
+fn cover_media_type(path: &str) -> Result<&'static str, Error> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    Ok(match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        _ => bail!("Unsupported cover image {}, expected PNG, JPEG, GIF or SVG", path),
+    })
+}
+
+fn cover_file_name(path: &str) -> String {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+    format!("cover.{}", extension.to_lowercase())
+}
+
+fn package(meta: &BookMetadata, identifier: &str, sections: &[Section]) -> Result<String, Error> {
+    let mut manifest = String::new();
+    let mut spine = String::new();
+
+    let mut creators = String::new();
+    for author in &meta.authors {
+        creators.push_str(&format!("<dc:creator>{}</dc:creator>\n", escape(author)));
+    }
+
+    let mut metadata = String::new();
+    for other in meta.identifiers.iter().skip(1) {
+        metadata.push_str(&format!("<dc:identifier>{}</dc:identifier>\n", escape(&other.text)));
+    }
+    if !meta.rights.is_empty() {
+        metadata.push_str(&format!("<dc:rights>{}</dc:rights>\n", escape(&meta.rights)));
+    }
+    if let Some(ref cover) = meta.cover {
+        // The EPUB 2 way of marking the cover, which KindleGen needs
+        metadata.push_str("<meta name=\"cover\" content=\"cover-image\"/>\n");
+        manifest.push_str(&format!(
+            "<item id=\"cover-image\" href=\"{}\" media-type=\"{}\" properties=\"cover-image\"/>\n",
+            cover_file_name(cover),
+            cover_media_type(cover)?,
+        ));
+    }
+
+    // Kindle conversion starts reading at the first chapter
+    let guide = sections
+        .first()
+        .map(|section| format!(
+            "<reference type=\"text\" title=\"{}\" href=\"{}\"/>\n",
+            escape(&section.title),
+            section.file_name(EXTENSION),
+        ))
+        .unwrap_or_default();
+
+    for (index, section) in sections.iter().enumerate() {
+        manifest.push_str(&format!(
+            "<item id=\"ch{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+            index,
+            section.file_name(EXTENSION),
+        ));
+        spine.push_str(&format!("<itemref idref=\"ch{}\"/>\n", index));
+    }
+
+    Ok(templates::PACKAGE
+        .replace("{identifier}", &escape(identifier))
+        .replace("{title}", &escape(&meta.title))
+        .replace("{creators}", &creators)
+        .replace("{lang}", &escape(&meta.language))
+        .replace("{date}", &escape(&meta.date))
+        .replace("{description}", &escape(&meta.description))
+        .replace("{metadata}", &metadata)
+        .replace("{manifest}", &manifest)
+        .replace("{spine}", &spine)
+        .replace("{guide}", &guide))
+}
+
+fn navigation(meta: &BookMetadata, points: &[NavPoint]) -> String {
+    let mut list = String::new();
+    nav_list(points, &mut list);
+
+    xhtml_document(
+        &meta.title,
+        &meta.language,
+        &format!(
+            "<nav epub:type=\"toc\" id=\"toc\">\n<h1>Table of Contents</h1>\n{}</nav>\n",
+            list
+        ),
+    )
+}
+
+fn ncx(meta: &BookMetadata, identifier: &str, points: &[NavPoint]) -> String {
+    let mut nav_map = String::new();
+    ncx_points(points, &mut 0, &mut nav_map);
+
+    templates::NCX
+        .replace("{identifier}", &escape(identifier))
+        .replace("{title}", &escape(&meta.title))
+        .replace("{nav_map}", &nav_map)
+}
+
+/// Writes the book as an EPUB to `path`
+pub fn write<P: AsRef<Path>>(
+    meta: &BookMetadata,
+    body: &str,
+    contents: &[Contents],
+    output: &Output,
+    path: P,
+) -> Result<(), Error> {
+    let identifier = identifier(meta, output);
+    let sections = sections::split(body);
+
+    let mut renderer = Renderer::new();
+    let mut documents: Vec<(String, String)> = sections
+        .iter()
+        .map(|section| (section.file_name(EXTENSION), renderer.render(&section.markdown)))
+        .collect();
+    sections::link_documents(&mut documents);
+
+    let points = sections::nav_tree(&sections, contents);
+    let stylesheet = output.stylesheet(Format::Epub, &[STYLESHEET])?;
+
+    let mut epub = ZipWriter::new(File::create(path)?);
+    let deflated = FileOptions::default();
+
+    // The mimetype must come first and must not be compressed
+    epub.start_file(
+        "mimetype",
+        FileOptions::default().compression_method(CompressionMethod::Stored)
+    )?;
+    epub.write_all(b"application/epub+zip")?;
+
+    epub.start_file("META-INF/container.xml", deflated)?;
+    epub.write_all(templates::CONTAINER.as_bytes())?;
+
+    epub.start_file("OEBPS/content.opf", deflated)?;
+    epub.write_all(package(meta, &identifier, &sections)?.as_bytes())?;
+
+    epub.start_file("OEBPS/nav.xhtml", deflated)?;
+    epub.write_all(navigation(meta, &points).as_bytes())?;
+
+    epub.start_file("OEBPS/toc.ncx", deflated)?;
+    epub.write_all(ncx(meta, &identifier, &points).as_bytes())?;
+
+    epub.start_file("OEBPS/style.css", deflated)?;
+    epub.write_all(stylesheet.as_bytes())?;
+    epub.write_all(output.highlighting(Format::Epub)?.as_bytes())?;
+
+    if let Some(ref cover) = meta.cover {
+        let image = fs::read(cover)
+            .map_err(|e| format_err!("Failed to read cover image {}: {}", cover, e))?;
+        epub.start_file(format!("OEBPS/{}", cover_file_name(cover)), deflated)?;
+        epub.write_all(&image)?;
+    }
+
+    for (section, (file_name, html)) in sections.iter().zip(documents) {
+        epub.start_file(format!("OEBPS/{}", file_name), deflated)?;
+        epub.write_all(xhtml_document(&section.title, &meta.language, &html).as_bytes())?;
+    }
+
+    epub.finish()?;
+    Ok(())
+}
+
+pub fn render_to_file(
+    meta: &BookMetadata,
+    body: &str,
+    contents: &[Contents],
+    output: &Output
+) -> Result<(), Error> {
+    write(meta, body, contents, output, output.path(Format::Epub))
+}
@@ -0,0 +1,620 @@
+//! Syntax highlighting that produces the same markup and classes as Pandoc,
+//! so the highlighting themes apply to both.
+
+use super::{latex::escape_verbatim, markup::escape, theme};
+use prepare::markdown::hidden_line;
+
+/// What highlighted code is written as
+#[derive(Clone, Copy)]
+enum Markup {
+    /// Spans with Pandoc's classes
+    Html,
+    /// Pandoc's `\KeywordTok{…}` macros, for the `Highlighting` environment
+    Latex,
+}
+
+/// The words a language's lexer tells apart, and how it runs
+struct Language {
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    constants: &'static [&'static str],
+    /// Words followed by `(` are function calls, capitalized ones types
+    calls_and_types: bool,
+    run: fn(&mut Lexer),
+}
+
+const RUST: Language = Language {
+    keywords: &[
+        "as", "box", "break", "const", "continue", "crate", "else", "enum",
+        "extern", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+        "move", "mut", "pub", "ref", "return", "self", "static", "struct", "super",
+        "trait", "type", "unsafe", "use", "where", "while",
+    ],
+    types: &[
+        "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8",
+        "i16", "i32", "i64", "i128", "isize", "f32", "f64", "Self",
+    ],
+    constants: &["true", "false"],
+    calls_and_types: true,
+    run: |lexer| lexer.rust(),
+};
+
+const C: Language = Language {
+    keywords: &[
+        "break", "case", "const", "continue", "default", "do", "else", "enum",
+        "extern", "for", "goto", "if", "inline", "register", "return", "sizeof",
+        "static", "struct", "switch", "typedef", "union", "volatile", "while",
+    ],
+    types: &[
+        "char", "double", "float", "int", "long", "short", "signed", "unsigned",
+        "void", "size_t", "int8_t", "int16_t", "int32_t", "int64_t", "uint8_t",
+        "uint16_t", "uint32_t", "uint64_t",
+    ],
+    constants: &["NULL"],
+    calls_and_types: false,
+    run: |lexer| lexer.c(),
+};
+
+const SHELL: Language = Language {
+    keywords: &[
+        "case", "do", "done", "elif", "else", "esac", "export", "fi", "for",
+        "function", "if", "in", "local", "then", "until", "while",
+    ],
+    types: &[],
+    constants: &[],
+    calls_and_types: false,
+    run: |lexer| lexer.shell(),
+};
+
+const TOML: Language = Language {
+    keywords: &[],
+    types: &[],
+    constants: &["true", "false"],
+    calls_and_types: false,
+    run: |lexer| lexer.toml(),
+};
+
+struct Lexer<'a> {
+    chars: Vec<char>,
+    position: usize,
+    language: &'a Language,
+    markup: Markup,
+    output: &'a mut String,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).cloned()
+    }
+
+    fn emit(&mut self, class: Option<&str>, end: usize) {
+        let end = end.min(self.chars.len());
+        let token: String = self.chars[self.position..end].iter().collect();
+        self.position = end;
+
+        match (self.markup, class) {
+            (Markup::Html, Some(class)) => self.output.push_str(&format!(
+                "<span class=\"{}\">{}</span>",
+                class,
+                escape(&token)
+            )),
+            (Markup::Html, None) => self.output.push_str(&escape(&token)),
+            (Markup::Latex, Some(class)) => self.output.push_str(&format!(
+                "\\{}Tok{{{}}}",
+                theme::token(class).unwrap_or("Other"),
+                escape_verbatim(&token)
+            )),
+            (Markup::Latex, None) => self.output.push_str(&escape_verbatim(&token)),
+        }
+    }
+
+    fn find_from(&self, start: usize, pattern: &[char]) -> usize {
+        (start..self.chars.len())
+            .find(|&i| self.chars[i..].starts_with(pattern))
+            .map(|i| i + pattern.len())
+            .unwrap_or_else(|| self.chars.len())
+    }
+
+    fn scan_while<F: Fn(char) -> bool>(&self, start: usize, predicate: F) -> usize {
+        (start..self.chars.len())
+            .find(|&i| !predicate(self.chars[i]))
+            .unwrap_or_else(|| self.chars.len())
+    }
+
+    fn string_end(&self, start: usize) -> usize {
+        let mut i = start;
+        while i < self.chars.len() {
+            match self.chars[i] {
+                '\\' => i += 2,
+                '"' => return i + 1,
+                _ => i += 1,
+            }
+        }
+        self.chars.len()
+    }
+
+    /// The end of a string opened by the `quote` at `start`, if it ends on its line
+    fn line_string_end(&self, start: usize, quote: char) -> Option<usize> {
+        let mut i = start + 1;
+        while let Some(&chr) = self.chars.get(i) {
+            match chr {
+                '\\' if quote == '"' => i += 2,
+                '\n' => return None,
+                chr if chr == quote => return Some(i + 1),
+                _ => i += 1,
+            }
+        }
+        None
+    }
+
+    fn block_comment_end(&self) -> usize {
+        let (mut i, mut depth) = (self.position, 0);
+        while i < self.chars.len() {
+            if self.chars[i..].starts_with(&['/', '*']) {
+                depth += 1;
+                i += 2;
+            } else if self.chars[i..].starts_with(&['*', '/']) {
+                depth -= 1;
+                i += 2;
+                if depth == 0 { return i }
+            } else {
+                i += 1;
+            }
+        }
+        i
+    }
+
+    /// `r"…"`, `r#"…"#`, `br"…"`
+    fn raw_string_end(&self, start: usize) -> Option<usize> {
+        let hashes = self.scan_while(start, |c| c == '#') - start;
+        if self.chars.get(start + hashes) != Some(&'"') { return None }
+
+        let mut terminator = vec!['"'];
+        terminator.extend(::std::iter::repeat('#').take(hashes));
+        Some(self.find_from(start + hashes + 1, &terminator))
+    }
+
+    /// `quote` is the position of the opening `'`
+    fn character_end(&self, quote: usize) -> Option<usize> {
+        match (self.chars.get(quote + 1), self.chars.get(quote + 2)) {
+            (Some('\\'), _) => Some(self.find_from(quote + 3, &['\''])),
+            (Some(_), Some('\'')) => Some(quote + 3),
+            _ => None,
+        }
+    }
+
+    /// Whether only whitespace precedes the position on its line
+    fn at_line_start(&self) -> bool {
+        self.chars[..self.position]
+            .iter()
+            .rev()
+            .take_while(|&&c| c != '\n')
+            .all(|c| c.is_whitespace())
+    }
+
+    fn line_end(&self) -> usize {
+        self.scan_while(self.position, |c| c != '\n')
+    }
+
+    fn word(&mut self) {
+        let end = self.scan_while(self.position, |c| c.is_alphanumeric() || c == '_');
+        let word: String = self.chars[self.position..end].iter().collect();
+        let next = self.chars[end..].iter().find(|c| !c.is_whitespace()).cloned();
+        let language = self.language;
+
+        let class = if language.keywords.contains(&word.as_str()) {
+            Some("kw")
+        } else if language.types.contains(&word.as_str()) {
+            Some("dt")
+        } else if language.constants.contains(&word.as_str()) {
+            Some("cn")
+        } else if !language.calls_and_types {
+            None
+        } else if self.chars.get(end) == Some(&'!') {
+            return self.emit(Some("fu"), end + 1);
+        } else if next == Some('(') {
+            Some("fu")
+        } else if word.starts_with(char::is_uppercase) {
+            Some("dt")
+        } else {
+            None
+        };
+
+        self.emit(class, end)
+    }
+
+    fn number(&mut self) {
+        let mut end = self.scan_while(self.position, |c| c.is_alphanumeric() || c == '_');
+        let is_float = self.chars.get(end) == Some(&'.')
+            && self.chars.get(end + 1).map_or(false, |c| c.is_digit(10));
+
+        if is_float {
+            end = self.scan_while(end + 1, |c| c.is_alphanumeric() || c == '_');
+        }
+
+        self.emit(Some(if is_float { "fl" } else { "dv" }), end)
+    }
+
+    fn rust(&mut self) {
+        while let Some(chr) = self.peek(0) {
+            let start = self.position;
+            match (chr, self.peek(1)) {
+                ('/', Some('/')) => {
+                    let end = self.line_end();
+                    self.emit(Some("co"), end)
+                }
+                ('/', Some('*')) => {
+                    let end = self.block_comment_end();
+                    self.emit(Some("co"), end)
+                }
+                ('#', Some('[')) | ('#', Some('!')) if self.peek(2) != Some('/') => {
+                    let end = self.find_from(start, &[']']);
+                    self.emit(Some("at"), end)
+                }
+                ('"', _) => {
+                    let end = self.string_end(start + 1);
+                    self.emit(Some("st"), end)
+                }
+                ('b', Some('"')) => {
+                    let end = self.string_end(start + 2);
+                    self.emit(Some("st"), end)
+                }
+                ('b', Some('\'')) => match self.character_end(start + 1) {
+                    Some(end) => self.emit(Some("ch"), end),
+                    None => self.word(),
+                },
+                ('r', Some('"')) | ('r', Some('#')) => match self.raw_string_end(start + 1) {
+                    Some(end) => self.emit(Some("st"), end),
+                    None => self.word(),
+                },
+                ('b', Some('r')) => match self.raw_string_end(start + 2) {
+                    Some(end) => self.emit(Some("st"), end),
+                    None => self.word(),
+                },
+                ('\'', _) => match self.character_end(start) {
+                    Some(end) => self.emit(Some("ch"), end),
+                    None => {
+                        // A lifetime
+                        let end = self.scan_while(start + 1, |c| c.is_alphanumeric() || c == '_');
+                        self.emit(None, end)
+                    }
+                },
+                (c, _) if c.is_digit(10) => self.number(),
+                (c, _) if c.is_alphabetic() || c == '_' => self.word(),
+                _ => self.emit(None, start + 1),
+            }
+        }
+    }
+
+    fn c(&mut self) {
+        while let Some(chr) = self.peek(0) {
+            let start = self.position;
+            match (chr, self.peek(1)) {
+                ('/', Some('/')) => {
+                    let end = self.line_end();
+                    self.emit(Some("co"), end)
+                }
+                ('/', Some('*')) => {
+                    let end = self.find_from(start + 2, &['*', '/']);
+                    self.emit(Some("co"), end)
+                }
+                ('#', _) if self.at_line_start() => {
+                    let end = self.line_end();
+                    self.emit(Some("pp"), end)
+                }
+                ('"', _) => {
+                    let end = self.string_end(start + 1);
+                    self.emit(Some("st"), end)
+                }
+                ('\'', _) => match self.character_end(start) {
+                    Some(end) => self.emit(Some("ch"), end),
+                    None => self.emit(None, start + 1),
+                },
+                (c, _) if c.is_digit(10) => self.number(),
+                (c, _) if c.is_alphabetic() || c == '_' => self.word(),
+                _ => self.emit(None, start + 1),
+            }
+        }
+    }
+
+    fn shell(&mut self) {
+        while let Some(chr) = self.peek(0) {
+            let start = self.position;
+            let after_space = start == 0 || self.chars[start - 1].is_whitespace();
+            let command_start = start == 0 || " \t\n;|&(".contains(self.chars[start - 1]);
+            match (chr, self.peek(1)) {
+                ('#', _) if after_space => {
+                    let end = self.line_end();
+                    self.emit(Some("co"), end)
+                }
+                ('"', _) | ('\'', _) => match self.line_string_end(start, chr) {
+                    Some(end) => self.emit(Some("st"), end),
+                    None => self.emit(None, start + 1),
+                },
+                ('$', Some('{')) => {
+                    let end = self.find_from(start + 2, &['}']);
+                    self.emit(Some("va"), end)
+                }
+                ('$', Some(c)) if c.is_alphabetic() || c == '_' => {
+                    let end = self.scan_while(start + 1, |c| c.is_alphanumeric() || c == '_');
+                    self.emit(Some("va"), end)
+                }
+                // Keywords start commands, not paths or options
+                (c, _) if (c.is_alphanumeric() || c == '_') && command_start => self.word(),
+                (c, _) if c.is_alphanumeric() || c == '_' => {
+                    let end = self.scan_while(start, |c| c.is_alphanumeric() || c == '_');
+                    self.emit(None, end)
+                }
+                _ => self.emit(None, start + 1),
+            }
+        }
+    }
+
+    fn toml(&mut self) {
+        while let Some(chr) = self.peek(0) {
+            let start = self.position;
+            match chr {
+                '#' => {
+                    let end = self.line_end();
+                    self.emit(Some("co"), end)
+                }
+                '[' if self.at_line_start() => {
+                    let end = self.find_from(start, &[']']);
+                    let end = self.scan_while(end, |c| c == ']');
+                    self.emit(Some("kw"), end)
+                }
+                '"' | '\'' => match self.line_string_end(start, chr) {
+                    Some(end) => self.emit(Some("st"), end),
+                    None => self.emit(None, start + 1),
+                },
+                c if (c.is_alphanumeric() || c == '_' || c == '-') && self.at_line_start() => {
+                    // A key
+                    let end = self.scan_while(start, |c| c.is_alphanumeric() || "_-.".contains(c));
+                    self.emit(Some("va"), end)
+                }
+                c if c.is_digit(10) => self.number(),
+                c if c.is_alphabetic() => self.word(),
+                _ => self.emit(None, start + 1),
+            }
+        }
+    }
+}
+
+fn highlight(language: &Language, code: &str, markup: Markup) -> String {
+    let mut output = String::with_capacity(code.len() * 2);
+    let mut lexer = Lexer {
+        chars: code.chars().collect(),
+        position: 0,
+        language,
+        markup,
+        output: &mut output,
+    };
+    (language.run)(&mut lexer);
+    output
+}
+
+fn rust(code: &str) -> String {
+    highlight(&RUST, code, Markup::Html)
+}
+
+/// The lexer of the code block language `name`, other than `text`
+fn language(name: &str) -> Option<&'static Language> {
+    match name {
+        "rust" => Some(&RUST),
+        "c" => Some(&C),
+        "sh" | "bash" | "shell" => Some(&SHELL),
+        "toml" => Some(&TOML),
+        _ => None,
+    }
+}
+
+/// Whether the `code-blocks` pass kept the hidden lines of a block with the `classes`
+pub fn keeps_hidden_lines(classes: &[&str]) -> bool {
+    classes.contains(&"hidden-grey") || classes.contains(&"hidden-toggle")
+}
+
+/// Highlighted Rust code, its runs of hidden lines in `<span class="hidden">`
+fn rust_with_hidden(code: &str) -> String {
+    let mut output = String::with_capacity(code.len() * 2);
+    let mut run = String::new();
+    let mut run_hidden = false;
+
+    let mut flush = |run: &mut String, hidden: bool| {
+        if run.is_empty() { return }
+        if hidden {
+            output.push_str(&format!("<span class=\"hidden\">{}</span>", rust(run)));
+        } else {
+            output.push_str(&rust(run));
+        }
+        run.clear();
+    };
+
+    for line in code.split_terminator('\n') {
+        let (text, hidden) = match hidden_line(line) {
+            Some(text) => (text, true),
+            None => (line, false),
+        };
+        if hidden != run_hidden {
+            flush(&mut run, run_hidden);
+            run_hidden = hidden;
+        }
+        run.push_str(text);
+        run.push('\n');
+    }
+    flush(&mut run, run_hidden);
+
+    output
+}
+
+/// What code block attributes tell readers, like the badges of the online book
+const CAPTIONS: &[(&str, &str)] = &[
+    ("compile_fail", "This code does not compile"),
+    ("ignore",       "This example is not tested"),
+    ("should_panic", "This code panics"),
+];
+
+/// The caption of a code block with the `classes`, if they tell readers anything
+pub fn caption(classes: &[&str]) -> Option<&'static str> {
+    CAPTIONS
+        .iter()
+        .find(|(attribute, _)| classes.contains(attribute))
+        .map(|(_, caption)| *caption)
+}
+
+/// Highlighted markup for a code block, `None` if its language,
+/// the first of its `classes`, is unsupported.
+/// `text` is tagged but not highlighted.
+/// Like Pandoc, all classes go on the `pre`.
+/// Kept hidden lines can be shown with a checkbox with the id `toggle`.
+pub fn code_block(classes: &[&str], code: &str, toggle: Option<&str>) -> Option<String> {
+    let language = classes.first().cloned().unwrap_or("");
+    let highlighted = match language {
+        "rust" if keeps_hidden_lines(classes) => rust_with_hidden(code),
+        "text" | "txt" => escape(code),
+        name => highlight(self::language(name)?, code, Markup::Html),
+    };
+
+    let toggle = toggle.map_or_else(String::new, |id| format!(
+        "<input type=\"checkbox\" class=\"hidden-toggle\" id=\"{id}\" />\
+         <label class=\"hidden-toggle\" for=\"{id}\">Show hidden lines</label>",
+        id = id,
+    ));
+
+    Some(format!(
+        "<div class=\"sourceCode\">{toggle}<pre class=\"sourceCode {classes}\">\
+         <code class=\"sourceCode {lang}\">{code}</code></pre></div>\n",
+        toggle = toggle,
+        classes = classes.join(" "),
+        lang = language,
+        code = highlighted,
+    ))
+}
+
+/// A highlighted code block in the environments `Theme::latex` defines,
+/// `None` if its language, the first of its `classes`, is unsupported
+pub fn latex_code_block(classes: &[&str], code: &str) -> Option<String> {
+    let highlighted = match classes.first().cloned().unwrap_or("") {
+        "text" | "txt" => escape_verbatim(code),
+        name => highlight(language(name)?, code, Markup::Latex),
+    };
+
+    Some(format!(
+        "\\begin{{Shaded}}\n\\begin{{Highlighting}}[]\n{}{}\\end{{Highlighting}}\n\\end{{Shaded}}\n",
+        highlighted,
+        if highlighted.ends_with('\n') { "" } else { "\n" },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rust_tokens() {
+        assert_eq!(
+            indoc!(r#"
+                <span class="at">#[derive(Debug)]</span>
+                <span class="kw">fn</span> <span class="fu">main</span>() { <span class="co">// hi</span>
+                    <span class="kw">let</span> x: <span class="dt">Vec</span>&lt;&amp;<span class="dt">str</span>&gt; = <span class="fu">vec!</span>[<span class="st">&quot;a\&quot;&quot;</span>, <span class="st">r#&quot;b&quot;#</span>];
+                    <span class="kw">let</span> c = (<span class="ch">'c'</span>, <span class="dv">1_000</span>, <span class="fl">2.5</span>, <span class="dv">0</span>..<span class="dv">2</span>, <span class="cn">true</span>);
+                }
+                "#),
+            rust(indoc!(r##"
+                #[derive(Debug)]
+                fn main() { // hi
+                    let x: Vec<&str> = vec!["a\"", r#"b"#];
+                    let c = ('c', 1_000, 2.5, 0..2, true);
+                }
+                "##))
+        );
+    }
+
+    #[test]
+    fn other_languages() {
+        assert_eq!(
+            indoc!(r#"
+                <span class="pp">#include &lt;stdio.h&gt;</span>
+                <span class="dt">int</span> main(<span class="dt">void</span>) { <span class="kw">return</span> <span class="dv">0</span>; } <span class="co">/* done */</span>
+                "#),
+            highlight(&C, indoc!("
+                #include <stdio.h>
+                int main(void) { return 0; } /* done */
+                "), Markup::Html)
+        );
+        assert_eq!(
+            indoc!(r#"
+                $ cargo build <span class="co"># or run</span>
+                <span class="kw">export</span> PATH=<span class="st">&quot;$HOME/bin&quot;</span>:<span class="va">$PATH</span>
+                "#),
+            highlight(&SHELL, indoc!(r#"
+                $ cargo build # or run
+                export PATH="$HOME/bin":$PATH
+                "#), Markup::Html)
+        );
+        assert_eq!(
+            "error: can't find /usr/local/lib\n",
+            highlight(&SHELL, "error: can't find /usr/local/lib\n", Markup::Html)
+        );
+        assert_eq!(
+            indoc!(r#"
+                <span class="kw">[package]</span>
+                <span class="va">name</span> = <span class="st">&quot;hello_world&quot;</span> <span class="co"># the crate</span>
+                <span class="va">publish</span> = <span class="cn">false</span>
+                "#),
+            highlight(&TOML, indoc!(r#"
+                [package]
+                name = "hello_world" # the crate
+                publish = false
+                "#), Markup::Html)
+        );
+    }
+
+    #[test]
+    fn lifetimes() {
+        assert_eq!(
+            "&amp;'a <span class=\"dt\">T</span>",
+            rust("&'a T")
+        );
+        assert_eq!(None, code_block(&["cobol"], "DISPLAY 'HI'.", None));
+    }
+
+    #[test]
+    fn latex_tokens() {
+        assert_eq!(
+            indoc!(r#"
+                \begin{Shaded}
+                \begin{Highlighting}[]
+                \KeywordTok{let} s = \StringTok{"\textbackslash{}\{"};
+                \end{Highlighting}
+                \end{Shaded}
+                "#),
+            latex_code_block(&["rust"], "let s = \"\\{\";").unwrap()
+        );
+        assert_eq!(None, latex_code_block(&["cobol"], "DISPLAY 'HI'."));
+    }
+
+    #[test]
+    fn attributes() {
+        assert_eq!(Some("This code panics"), caption(&["rust", "no_run", "should_panic"]));
+        assert_eq!(Some("This example is not tested"), caption(&["rust", "ignore"]));
+        assert_eq!(None, caption(&["rust", "no_run"]));
+        assert_eq!(
+            "<div class=\"sourceCode\"><pre class=\"sourceCode rust compile_fail\">\
+             <code class=\"sourceCode rust\">x</code></pre></div>\n",
+            code_block(&["rust", "compile_fail"], "x", None).unwrap()
+        );
+    }
+
+    #[test]
+    fn hidden_lines() {
+        assert_eq!(
+            indoc!(r#"
+                <div class="sourceCode"><input type="checkbox" class="hidden-toggle" id="hidden-lines-1" /><label class="hidden-toggle" for="hidden-lines-1">Show hidden lines</label><pre class="sourceCode rust hidden-toggle"><code class="sourceCode rust"><span class="hidden"><span class="kw">fn</span> <span class="fu">main</span>() {
+                </span>x;
+                <span class="hidden">}
+                </span></code></pre></div>
+                "#),
+            code_block(&["rust", "hidden-toggle"], "# fn main() {\nx;\n# }\n", Some("hidden-lines-1")).unwrap()
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use super::{
+    markup::{escape, Heading, Renderer},
+    template::{self, Context},
+    Format,
+    Output,
+};
+use aux::file;
+use metadata::BookMetadata;
+use failure::{Error, ResultExt};
+
+const TEMPLATE: &str = "lib/template.html";
+const STYLESHEET: &str = "lib/pandoc.css";
+
+mod reg {
+    pub const LINK_TAG: &str = r"</?a(\s[^>]*)?>";
+}
+
+fn toc_list(headings: &[&Heading], position: &mut usize, level: usize, output: &mut String) {
+    lazy_static_regex!(LINK_TAG, reg::LINK_TAG);
+
+    output.push_str("<ul>\n");
+    while let Some(heading) = headings.get(*position) {
+        if heading.level < level { break }
+        *position += 1;
+
+        output.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            escape(&heading.id),
+            LINK_TAG.replace_all(&heading.html, ""),
+        ));
+        let has_children = headings
+            .get(*position)
+            .map_or(false, |next| next.level > heading.level);
+        if has_children { toc_list(headings, position, heading.level + 1, output) }
+        output.push_str("</li>\n");
+    }
+    output.push_str("</ul>\n");
+}
+
+/// Pandoc's `--table-of-contents`
+fn table_of_contents(headings: &[Heading], depth: usize) -> String {
+    let headings: Vec<&Heading> = headings
+        .iter()
+        .filter(|heading| heading.level <= depth)
+        .collect();
+
+    let mut toc = String::new();
+    toc_list(&headings, &mut 0, 1, &mut toc);
+    toc
+}
+
+pub fn render_to_file(
+    meta: &BookMetadata,
+    body: &str,
+    output: &Output
+) -> Result<(), Error> {
+    let mut renderer = Renderer::new();
+    let html = renderer.render(body);
+
+    let template = file::to_string(TEMPLATE).context("Failed to read HTML template")?;
+    let stylesheet = output.stylesheet(Format::Html, &[STYLESHEET])?;
+
+    let mut context = Context::new();
+    context
+        .set("locale", escape(&meta.language))
+        .set("pagetitle", escape(&meta.title))
+        .set("title", escape(&meta.title))
+        .set("author", escape(&meta.author()))
+        .set("author-meta", escape(&meta.author()))
+        .set("date", escape(&meta.date))
+        .set("date-meta", escape(&meta.date))
+        .set("highlighting-css", output.highlighting(Format::Html)?)
+        .set("header-includes", format!("<style type=\"text/css\">\n{}</style>", stylesheet))
+        .set("toc", table_of_contents(renderer.headings(), meta.toc_depth))
+        .set("body", html);
+
+    file::from_string(
+        output.path(Format::Html),
+        template::render(&template, &context)?
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toc() {
+        let mut renderer = Renderer::new();
+        renderer.render(indoc!("
+            # One {#sec--one}
+            ## [Nested](#sec--one)
+            ### Too deep
+            # Two
+            "));
+
+        assert_eq!(
+            indoc!(r##"
+                <ul>
+                <li><a href="#sec--one">One</a><ul>
+                <li><a href="#nested">Nested</a></li>
+                </ul>
+                </li>
+                <li><a href="#two">Two</a></li>
+                </ul>
+                "##),
+            table_of_contents(renderer.headings(), 2)
+        );
+    }
+}
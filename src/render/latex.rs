@@ -0,0 +1,392 @@
+use super::{
+    highlight,
+    markup::{self, Identifiers},
+    template::{self, Context},
+    Format,
+    Output,
+};
+use aux::file;
+use metadata::BookMetadata;
+use prepare::markdown::{hidden_line, info_attributes};
+use failure::{Error, ResultExt};
+use pulldown_cmark::{Alignment, Event, Tag};
+use std::collections::HashMap;
+
+const TEMPLATE: &str = "lib/template.tex";
+
+mod reg {
+    pub const SUPERSCRIPT: &str = r"\^(?P<text>[^\^\s]+)\^";
+}
+
+const BOOK_SECTIONS: &[&str] = &[
+    "chapter", "section", "subsection", "subsubsection", "paragraph", "subparagraph",
+];
+const ARTICLE_SECTIONS: &[&str] = &[
+    "section", "subsection", "subsubsection", "paragraph", "subparagraph",
+];
+
+fn has_chapters(documentclass: &str) -> bool {
+    documentclass == "book" || documentclass == "report"
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for chr in text.chars() {
+        match chr {
+            '\\' => escaped.push_str(r"\textbackslash{}"),
+            '~' => escaped.push_str(r"\textasciitilde{}"),
+            '^' => escaped.push_str(r"\^{}"),
+            '{' | '}' | '$' | '&' | '#' | '_' | '%' => {
+                escaped.push('\\');
+                escaped.push(chr);
+            }
+            _ => escaped.push(chr),
+        }
+    }
+    escaped
+}
+
+fn escape_url(url: &str) -> String {
+    url.replace('\\', "/").replace('#', r"\#").replace('%', r"\%")
+}
+
+/// Escapes text for a Verbatim environment with `commandchars=\\\{\}`
+pub fn escape_verbatim(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for chr in text.chars() {
+        match chr {
+            '\\' => escaped.push_str(r"\textbackslash{}"),
+            '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(chr);
+            }
+            _ => escaped.push(chr),
+        }
+    }
+    escaped
+}
+
+/// A Verbatim block printing the hidden lines of a Rust example greyed out
+fn hidden_lines_verbatim(code: &str) -> String {
+    let mut output = String::from("\\begin{Verbatim}[commandchars=\\\\\\{\\}]\n");
+    for line in code.split_terminator('\n') {
+        match hidden_line(line) {
+            Some(text) => output.push_str(&format!("\\textcolor{{gray}}{{{}}}\n", escape_verbatim(text))),
+            None => output.push_str(&format!("{}\n", escape_verbatim(line))),
+        }
+    }
+    output.push_str("\\end{Verbatim}\n");
+    output
+}
+
+fn superscript(text: &str) -> String {
+    lazy_static_regex!(SUPERSCRIPT, reg::SUPERSCRIPT);
+
+    let mut output = String::with_capacity(text.len());
+    let mut last = 0;
+    for capture in SUPERSCRIPT.captures_iter(text) {
+        let whole = capture.get(0).unwrap();
+        output.push_str(&escape(&text[last..whole.start()]));
+        output.push_str(&format!(r"\textsuperscript{{{}}}", escape(&capture["text"])));
+        last = whole.end();
+    }
+    output.push_str(&escape(&text[last..]));
+    output
+}
+
+/// Collects events up to the end of the current tag, which is dropped
+fn take_inner<'a, I>(events: &mut I) -> Vec<Event<'a>>
+where
+    I: Iterator<Item = Event<'a>>
+{
+    let mut depth = 0;
+    events
+        .take_while(|event| match event {
+            Event::Start(_) => { depth += 1; true }
+            Event::End(_) if depth == 0 => false,
+            Event::End(_) => { depth -= 1; true }
+            _ => true,
+        })
+        .collect()
+}
+
+/// Renders book markdown to LaTeX
+pub struct Writer {
+    identifiers: Identifiers,
+    sections: &'static [&'static str],
+    links_as_notes: bool,
+    in_code: bool,
+    table_cell: usize,
+}
+
+impl Writer {
+    pub fn new(meta: &BookMetadata) -> Self {
+        Writer {
+            identifiers: Identifiers::default(),
+            sections: if has_chapters(&meta.documentclass) { BOOK_SECTIONS } else { ARTICLE_SECTIONS },
+            links_as_notes: meta.links_as_notes,
+            in_code: false,
+            table_cell: 0,
+        }
+    }
+
+    fn start<'a, I>(&mut self, tag: Tag<'a>, events: &mut I, footnotes: &HashMap<String, Vec<Event<'a>>>, output: &mut String)
+    where
+        I: Iterator<Item = Event<'a>>
+    {
+        match tag {
+            Tag::Header(level) => {
+                let mut inner = take_inner(events);
+                let label = self.identifiers.header(&mut inner);
+                let mut title = String::new();
+                self.write(inner, footnotes, &mut title);
+
+                let index = (level as usize).saturating_sub(1).min(self.sections.len() - 1);
+                output.push_str(&format!(
+                    "\\{}{{{}}}\\label{{{}}}\n\n",
+                    self.sections[index],
+                    title.trim(),
+                    label,
+                ));
+            }
+            Tag::CodeBlock(info) => {
+                let code = markup::plain_text(&take_inner(events));
+                let classes: Vec<&str> = info_attributes(&info).collect();
+                if let Some(caption) = highlight::caption(&classes) {
+                    output.push_str(&format!("\\noindent\\textit{{{}}}\n", caption));
+                }
+                if highlight::keeps_hidden_lines(&classes) {
+                    output.push_str(&hidden_lines_verbatim(&code));
+                } else if let Some(highlighted) = highlight::latex_code_block(&classes, &code) {
+                    output.push_str(&highlighted);
+                } else {
+                    output.push_str("\\begin{Verbatim}\n");
+                    output.push_str(&code);
+                    if !code.ends_with('\n') { output.push('\n') }
+                    output.push_str("\\end{Verbatim}\n");
+                }
+                output.push('\n');
+            }
+            Tag::Image(destination, _) => {
+                take_inner(events);
+                output.push_str(&format!("\\includegraphics{{{}}}", escape_url(&destination)));
+            }
+            Tag::Paragraph => {},
+            Tag::Rule => output.push_str("\\begin{center}\\rule{0.5\\linewidth}{0.5pt}\\end{center}\n\n"),
+            Tag::BlockQuote => output.push_str("\\begin{quote}\n"),
+            Tag::List(None) => output.push_str("\\begin{itemize}\n"),
+            Tag::List(Some(start)) => {
+                output.push_str("\\begin{enumerate}\n");
+                if start != 1 { output.push_str(&format!("\\setcounter{{enumi}}{{{}}}\n", start - 1)) }
+            }
+            Tag::Item => output.push_str("\\item "),
+            Tag::Emphasis => output.push_str("\\emph{"),
+            Tag::Strong => output.push_str("\\textbf{"),
+            Tag::Code => {
+                self.in_code = true;
+                output.push_str("\\texttt{");
+            }
+            Tag::Link(destination, _) => {
+                if destination.starts_with('#') {
+                    output.push_str(&format!("\\hyperref[{}]{{", &destination[1..]));
+                } else if !self.links_as_notes {
+                    output.push_str(&format!("\\href{{{}}}{{", escape_url(&destination)));
+                }
+            }
+            Tag::Table(alignments) => {
+                let columns: String = alignments
+                    .iter()
+                    .map(|alignment| match alignment {
+                        Alignment::Center => 'c',
+                        Alignment::Right => 'r',
+                        _ => 'l',
+                    })
+                    .collect();
+                output.push_str(&format!("\\begin{{longtable}}[]{{@{{}}{}@{{}}}}\n\\hline\n", columns));
+            }
+            Tag::TableHead | Tag::TableRow => self.table_cell = 0,
+            Tag::TableCell => if self.table_cell > 0 { output.push_str(" & ") },
+            Tag::FootnoteDefinition(_) => { take_inner(events); }
+        }
+    }
+
+    fn end(&mut self, tag: Tag, output: &mut String) {
+        match tag {
+            Tag::Paragraph => output.push_str("\n\n"),
+            Tag::BlockQuote => output.push_str("\\end{quote}\n\n"),
+            Tag::List(None) => output.push_str("\\end{itemize}\n\n"),
+            Tag::List(Some(_)) => output.push_str("\\end{enumerate}\n\n"),
+            Tag::Item => output.push('\n'),
+            Tag::Emphasis | Tag::Strong => output.push('}'),
+            Tag::Code => {
+                self.in_code = false;
+                output.push('}');
+            }
+            Tag::Link(destination, _) => {
+                if destination.starts_with('#') || !self.links_as_notes {
+                    output.push('}');
+                } else {
+                    output.push_str(&format!("\\footnote{{\\url{{{}}}}}", escape_url(&destination)));
+                }
+            }
+            Tag::Table(_) => output.push_str("\\hline\n\\end{longtable}\n\n"),
+            Tag::TableHead => output.push_str(" \\\\\n\\hline\n\\endhead\n"),
+            Tag::TableRow => output.push_str(" \\\\\n"),
+            Tag::TableCell => self.table_cell += 1,
+            _ => {},
+        }
+    }
+
+    fn write<'a>(&mut self, events: Vec<Event<'a>>, footnotes: &HashMap<String, Vec<Event<'a>>>, output: &mut String) {
+        let mut events = events.into_iter();
+
+        while let Some(event) = events.next() {
+            match event {
+                Event::Start(tag) => self.start(tag, &mut events, footnotes, output),
+                Event::End(tag) => self.end(tag, output),
+                Event::Text(text) => if self.in_code {
+                    output.push_str(&escape(&text))
+                } else {
+                    output.push_str(&superscript(&text))
+                },
+                Event::Html(html) | Event::InlineHtml(html) => match html.trim() {
+                    "<sup>" => output.push_str("\\textsuperscript{"),
+                    "</sup>" => output.push('}'),
+                    _ => {},
+                },
+                Event::FootnoteReference(name) => if let Some(note) = footnotes.get(name.as_ref()) {
+                    let mut text = String::new();
+                    self.write(note.clone(), footnotes, &mut text);
+                    output.push_str(&format!("\\footnote{{{}}}", text.trim()));
+                },
+                Event::SoftBreak => output.push('\n'),
+                Event::HardBreak => output.push_str("\\\\\n"),
+            }
+        }
+    }
+
+    pub fn render(&mut self, markdown: &str) -> String {
+        let events: Vec<Event> = markup::parser(markdown).collect();
+
+        // LaTeX wants the footnote text where the footnote is referenced
+        let mut footnotes = HashMap::new();
+        let mut iter = events.iter().cloned();
+        while let Some(event) = iter.next() {
+            if let Event::Start(Tag::FootnoteDefinition(name)) = event {
+                footnotes.insert(name.into_owned(), take_inner(&mut iter));
+            }
+        }
+
+        let mut output = String::with_capacity(markdown.len() * 5 / 4);
+        self.write(events, &footnotes, &mut output);
+        output
+    }
+}
+
+/// Renders the aggregated book into a complete LaTeX document, `highlighting`
+/// are the definitions of the highlighting theme
+pub fn render_document(meta: &BookMetadata, body: &str, highlighting: &str) -> Result<String, Error> {
+    let template = file::to_string(TEMPLATE).context("Failed to read LaTeX template")?;
+
+    let documentclass = if meta.documentclass.is_empty() { "article" } else { &meta.documentclass };
+    let tocdepth = if has_chapters(documentclass) { meta.toc_depth.saturating_sub(1) } else { meta.toc_depth };
+
+    let mut context = Context::new();
+    context
+        .set("documentclass", documentclass)
+        .set("title", escape(&meta.title))
+        .set("author", escape(&meta.author()))
+        .set("date", escape(&meta.date))
+        .set("monofont", meta.monofont.as_str())
+        .set("tocdepth", tocdepth.to_string())
+        .set("highlighting-macros", highlighting)
+        .set("body", Writer::new(meta).render(body));
+    if meta.verbatim_in_note { context.set("verbatim-in-note", "true"); }
+
+    template::render(&template, &context)
+}
+
+pub fn render_to_file(
+    meta: &BookMetadata,
+    body: &str,
+    output: &Output
+) -> Result<(), Error> {
+    file::from_string(
+        output.path(Format::Latex),
+        render_document(meta, body, &output.latex_highlighting(Format::Latex)?)?
+    ).map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book() -> BookMetadata {
+        BookMetadata {
+            documentclass: "book".into(),
+            links_as_notes: true,
+            ..BookMetadata::default()
+        }
+    }
+
+    #[test]
+    fn sections_and_links() {
+        assert_eq!(
+            indoc!(r"
+                \chapter{Ownership \& \texttt{\&mut}}\label{sec--ownership}
+
+                \section{Moves}\label{moves}
+
+                See \hyperref[moves]{above} and the \emph{docs}\footnote{\url{http://doc.rust-lang.org/std/\#x}}.
+
+                "),
+            Writer::new(&book()).render(indoc!("
+                # Ownership & `&mut` {#sec--ownership}
+
+                ## Moves
+
+                See [above](#moves) and the [*docs*](http://doc.rust-lang.org/std/#x).
+                "))
+        );
+    }
+
+    #[test]
+    fn footnotes_and_code() {
+        assert_eq!(
+            indoc!(r"
+                2\textsuperscript{32} items\footnote{Or \textbf{more}.}
+
+                \begin{Shaded}
+                \begin{Highlighting}[]
+                \KeywordTok{let} x = \{\};
+                \end{Highlighting}
+                \end{Shaded}
+
+                "),
+            Writer::new(&BookMetadata::default()).render(indoc!("
+                2^32^ items[^n]
+
+                [^n]: Or **more**.
+
+                ```rust
+                let x = {};
+                ```
+                "))
+        );
+        assert_eq!(
+            "\\noindent\\textit{This code panics}\n\\begin{Verbatim}\nDISPLAY 'HI'.\n\\end{Verbatim}\n\n",
+            Writer::new(&BookMetadata::default()).render("```{.cobol .should_panic}\nDISPLAY 'HI'.\n```\n")
+        );
+        assert_eq!(
+            indoc!(r"
+                \begin{Verbatim}[commandchars=\\\{\}]
+                \textcolor{gray}{fn main() \{}
+                let x = \{\};
+                \textcolor{gray}{\}}
+                \end{Verbatim}
+
+                "),
+            Writer::new(&BookMetadata::default()).render("```{.rust .hidden-grey}\n# fn main() {\nlet x = {};\n# }\n```\n")
+        );
+    }
+}
@@ -0,0 +1,386 @@
+use super::{highlight, tables};
+use prepare::markdown::info_attributes;
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+mod reg {
+    pub const HEADER_ID:   &str = r"\s*\{#(?P<id>[^}\s]+)\}\s*$";
+    pub const SUPERSCRIPT: &str = r"\^(?P<text>[^\^\s]+)\^";
+    pub const HTML_TAG:    &str = r"^</?(?P<name>[A-Za-z][A-Za-z0-9]*)";
+    pub const HTML_ID:     &str = r#"\bid="(?P<id>[^"]+)""#;
+}
+
+/// Elements that Pandoc's `raw_html` passes through, anything else is text
+const HTML_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "blockquote", "br", "caption", "cite", "code", "col",
+    "colgroup", "dd", "del", "details", "div", "dl", "dt", "em", "figcaption",
+    "figure", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "i", "img", "ins",
+    "kbd", "li", "mark", "ol", "p", "pre", "q", "s", "samp", "small", "span",
+    "strong", "sub", "summary", "sup", "table", "tbody", "td", "tfoot", "th",
+    "thead", "tr", "u", "ul", "var",
+];
+
+fn is_html_element(html: &str) -> bool {
+    lazy_static_regex!(HTML_TAG, reg::HTML_TAG);
+
+    match HTML_TAG.captures(html.trim_start()) {
+        Some(tag) => HTML_ELEMENTS.contains(&tag["name"].to_lowercase().as_str()),
+        None => true, // Comments, entities
+    }
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for chr in text.chars() {
+        match chr {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(chr),
+        }
+    }
+    escaped
+}
+
+/// Pandoc's `auto_identifiers` algorithm
+fn auto_identifier(title: &str) -> String {
+    let identifier: String = title
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace() || "_-.".contains(*c))
+        .map(|c| if c.is_whitespace() { '-' } else { c })
+        .flat_map(char::to_lowercase)
+        .skip_while(|c| !c.is_alphabetic())
+        .collect();
+
+    if identifier.is_empty() { "section".into() } else { identifier }
+}
+
+/// The identifier Pandoc derives from a header's markdown `title`
+pub fn title_identifier(title: &str) -> String {
+    let events: Vec<Event> = parser(title).collect();
+    auto_identifier(&plain_text(&events))
+}
+
+fn superscript(text: &str) -> Cow<str> {
+    lazy_static_regex!(SUPERSCRIPT, reg::SUPERSCRIPT);
+
+    if !SUPERSCRIPT.is_match(text) { return Cow::from(text) }
+
+    let escaped = escape(text);
+    Cow::from(SUPERSCRIPT.replace_all(&escaped, "<sup>$text</sup>").into_owned())
+}
+
+/// Text content of `events`, without any markup
+pub fn plain_text(events: &[Event]) -> String {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Text(text) => Some(text.as_ref()),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn parser(markdown: &str) -> Parser {
+    Parser::new_ext(markdown, Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES)
+}
+
+/// Hands out header identifiers like Pandoc does, unique per document
+#[derive(Default)]
+pub struct Identifiers(HashMap<String, usize>);
+
+impl Identifiers {
+    fn unique(&mut self, identifier: String) -> String {
+        let count = self.0.entry(identifier.clone()).or_insert(0);
+        *count += 1;
+
+        match *count {
+            1 => identifier,
+            n => format!("{}-{}", identifier, n - 1),
+        }
+    }
+
+    /// Identifier for the header made of `inner` events, an explicit
+    /// trailing `{#id}` is removed from them and takes precedence.
+    pub fn header(&mut self, inner: &mut [Event]) -> String {
+        lazy_static_regex!(HEADER_ID, reg::HEADER_ID);
+
+        // pulldown-cmark splits text at `_`, so the id can span several events
+        let trailing_texts = inner
+            .iter()
+            .rev()
+            .take_while(|event| match event {
+                Event::Text(_) => true,
+                _ => false,
+            })
+            .count();
+        let first_text = inner.len() - trailing_texts;
+        let trailing: String = inner[first_text..]
+            .iter()
+            .filter_map(|event| match event {
+                Event::Text(text) => Some(text.as_ref()),
+                _ => None,
+            })
+            .collect();
+
+        let mut explicit_id = None;
+        if let Some(capture) = HEADER_ID.captures(&trailing) {
+            explicit_id = Some(capture["id"].to_string());
+            let mut title_left = capture.get(0).unwrap().start();
+            for event in &mut inner[first_text..] {
+                if let Event::Text(text) = event {
+                    let keep = title_left.min(text.len());
+                    title_left -= keep;
+                    *text = Cow::from(text[..keep].to_string());
+                }
+            }
+        }
+
+        let identifier = explicit_id.unwrap_or_else(|| auto_identifier(&plain_text(inner)));
+        self.unique(identifier)
+    }
+}
+
+/// Everything a `#fragment` link can point to: header identifiers
+/// as the renderers hand them out, and ids of raw HTML elements
+pub fn anchors(markdown: &str) -> HashSet<String> {
+    lazy_static_regex!(HTML_ID, reg::HTML_ID);
+
+    let mut identifiers = Identifiers::default();
+    let mut anchors = HashSet::new();
+    let mut parser = parser(markdown);
+
+    while let Some(event) = parser.next() {
+        match event {
+            Event::Start(Tag::Header(_)) => {
+                let mut inner: Vec<Event> = parser
+                    .by_ref()
+                    .take_while(|e| match e {
+                        Event::End(Tag::Header(_)) => false,
+                        _ => true,
+                    })
+                    .collect();
+                anchors.insert(identifiers.header(&mut inner));
+            }
+            Event::Html(html) | Event::InlineHtml(html) => {
+                anchors.extend(HTML_ID.captures_iter(&html).map(|capture| capture["id"].to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    anchors
+}
+
+/// A rendered header, for building tables of contents
+#[derive(Debug, PartialEq)]
+pub struct Heading {
+    pub level: usize,
+    pub id: String,
+    pub html: String,
+}
+
+/// Renders book markdown to XHTML compatible HTML fragments.
+///
+/// Header identifiers are unique across everything rendered by one
+/// `Renderer`, like they would be in a single Pandoc document.
+#[derive(Default)]
+pub struct Renderer {
+    identifiers: Identifiers,
+    headings: Vec<Heading>,
+    /// Code blocks with a hidden lines toggle so far, numbering their ids
+    toggles: usize,
+}
+
+impl Renderer {
+    pub fn new() -> Self { Self::default() }
+
+    /// Every header rendered so far, in document order
+    pub fn headings(&self) -> &[Heading] { &self.headings }
+
+    fn header<'a>(&mut self, level: i32, mut inner: Vec<Event<'a>>) -> Event<'a> {
+        let identifier = self.identifiers.header(&mut inner);
+
+        let mut contents = String::new();
+        html::push_html(&mut contents, inner.into_iter());
+
+        self.headings.push(Heading {
+            level: level as usize,
+            id: identifier.clone(),
+            html: contents.clone(),
+        });
+
+        Event::Html(Cow::from(format!(
+            "<h{level} id=\"{id}\">{contents}</h{level}>\n",
+            level = level,
+            id = escape(&identifier),
+            contents = contents,
+        )))
+    }
+
+    fn code_block<'a>(&mut self, info: Cow<'a, str>, code: Vec<Event<'a>>) -> Vec<Event<'a>> {
+        // The attributes of the info string, the language first
+        let classes: Vec<&str> = info_attributes(&info).collect();
+        let toggle = if classes.contains(&"hidden-toggle") {
+            self.toggles += 1;
+            Some(format!("hidden-lines-{}", self.toggles))
+        } else {
+            None
+        };
+
+        match highlight::code_block(&classes, &plain_text(&code), toggle.as_ref().map(String::as_str)) {
+            Some(html) => vec![Event::Html(Cow::from(html))],
+            None => {
+                let mut events = vec![Event::Start(Tag::CodeBlock(info.clone()))];
+                events.extend(code);
+                events.push(Event::End(Tag::CodeBlock(info)));
+                events
+            }
+        }
+    }
+
+    pub fn render(&mut self, markdown: &str) -> String {
+        let markdown = tables::replace_grid_tables(
+            markdown,
+            |cell| Renderer::new().render(cell)
+        );
+        let mut parser = parser(&markdown);
+
+        let mut events = Vec::new();
+        let mut in_code = false;
+
+        while let Some(event) = parser.next() {
+            let event = match event {
+                Event::Start(Tag::Header(level)) => {
+                    let inner = parser
+                        .by_ref()
+                        .take_while(|e| match e {
+                            Event::End(Tag::Header(_)) => false,
+                            _ => true,
+                        })
+                        .collect();
+                    self.header(level, inner)
+                }
+                Event::Start(Tag::CodeBlock(info)) => {
+                    let code = parser
+                        .by_ref()
+                        .take_while(|e| match e {
+                            Event::End(Tag::CodeBlock(_)) => false,
+                            _ => true,
+                        })
+                        .collect();
+                    events.extend(self.code_block(info, code));
+                    continue;
+                }
+                Event::Start(Tag::Code) => {
+                    in_code = true;
+                    Event::Start(Tag::Code)
+                }
+                Event::End(Tag::Code) => {
+                    in_code = false;
+                    Event::End(Tag::Code)
+                }
+                Event::InlineHtml(html) if !is_html_element(&html) => Event::Text(html),
+                Event::Text(text) if !in_code => match superscript(&text) {
+                    Cow::Owned(html) => Event::InlineHtml(Cow::from(html)),
+                    Cow::Borrowed(_) => Event::Text(text),
+                },
+                event => event,
+            };
+            events.push(event);
+        }
+
+        let mut output = String::with_capacity(markdown.len() * 3 / 2);
+        html::push_html(&mut output, events.into_iter());
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifiers() {
+        assert_eq!("hello-world", auto_identifier("Hello, World!"));
+        assert_eq!("section", auto_identifier("1.2"));
+        assert_eq!("ffi-and-c", auto_identifier("3. FFI and C"));
+        assert_eq!("the-try-macro", title_identifier("The `try!` macro"));
+        assert_eq!("see-vec", title_identifier("See [`Vec`](../std/vec/index.html)"));
+    }
+
+    #[test]
+    fn header_anchors() {
+        let anchors = anchors(indoc!(r#"
+            # Ownership {#sec--ownership}
+
+            ## Moves
+
+            ## Moves
+
+            <span id="code-unwrap-double"></span>
+
+            ```rust
+            # not a header
+            ```
+            "#));
+
+        let mut anchors: Vec<&str> = anchors.iter().map(String::as_str).collect();
+        anchors.sort();
+        assert_eq!(vec!["code-unwrap-double", "moves", "moves-1", "sec--ownership"], anchors);
+    }
+
+    #[test]
+    fn headers() {
+        let mut renderer = Renderer::new();
+        assert_eq!(
+            "<h1 id=\"sec--ffi\">FFI <code>C</code></h1>\n",
+            renderer.render("# FFI `C` {#sec--ffi}")
+        );
+        assert_eq!(
+            "<h2 id=\"examples\">Examples</h2>\n<h2 id=\"examples-1\">Examples</h2>\n",
+            renderer.render("## Examples\n\n## Examples")
+        );
+        assert_eq!(
+            Heading { level: 1, id: "sec--ffi".into(), html: "FFI <code>C</code>".into() },
+            renderer.headings()[0]
+        );
+        assert_eq!(
+            "<h2 id=\"sec--macros--assert-and-assert_eq\">assert! and assert_eq!</h2>\n",
+            renderer.render("## assert! and assert_eq! {#sec--macros--assert-and-assert_eq}")
+        );
+    }
+
+    #[test]
+    fn code_blocks() {
+        assert_eq!(
+            indoc!(r#"
+                <div class="sourceCode"><pre class="sourceCode rust ignore"><code class="sourceCode rust"><span class="kw">let</span> x;
+                </code></pre></div>
+                <div class="sourceCode"><pre class="sourceCode text"><code class="sourceCode text">2^32^
+                </code></pre></div>
+                "#),
+            Renderer::new().render("```{.rust .ignore}\nlet x;\n```\n\n```text\n2^32^\n```\n")
+        );
+    }
+
+    #[test]
+    fn raw_html() {
+        assert_eq!(
+            "<p><i>Vec&lt;T&gt;</i></p>\n",
+            Renderer::new().render("<i>Vec<T></i>")
+        );
+    }
+
+    #[test]
+    fn superscripts() {
+        assert_eq!(
+            "<p>2<sup>32</sup> &amp; <code>2^32^</code></p>\n",
+            Renderer::new().render("2^32^ & `2^32^`")
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Kindle output, converted from our EPUB by Amazon's KindleGen.
+//! KindleGen maps the EPUB's NCX and `<guide>` onto the Kindle index
+//! and writes both a MOBI 7 and a KF8 (AZW3) version into the file.
+
+use super::{epub, Format, Output};
+use failure::{Error, ResultExt};
+use metadata::BookMetadata;
+use prepare::Contents;
+use std::{
+    env,
+    fs,
+    process::Command,
+};
+
+const KINDLEGEN: &str = "kindlegen";
+
+/// KindleGen exits with 1 when it only emitted warnings
+const EXIT_WARNINGS: i32 = 1;
+
+/// The `Error(…)` lines of KindleGen's output, or all of it if there are none
+fn errors(output: &str) -> String {
+    let errors: Vec<&str> = output
+        .lines()
+        .filter(|line| line.starts_with("Error"))
+        .collect();
+
+    if errors.is_empty() { output.trim().to_string() } else { errors.join("\n") }
+}
+
+pub fn render_to_file(
+    meta: &BookMetadata,
+    body: &str,
+    contents: &[Contents],
+    output: &Output
+) -> Result<(), Error> {
+    let name = output.stem();
+    let build_dir = env::temp_dir().join(format!("compile-trpl-{}", name));
+    fs::create_dir_all(&build_dir)?;
+
+    let source = build_dir.join(format!("{}.epub", name));
+    // Converted from the EPUB, so it uses the EPUB stylesheet, but its own theme
+    let mut epub_output = output.clone();
+    epub_output.themes.insert(Format::Epub, output.theme(Format::Mobi).to_string());
+    epub::write(meta, body, contents, &epub_output, &source)?;
+
+    let kindlegen = Command::new(KINDLEGEN)
+        .arg(&source)
+        .arg("-o")
+        .arg(format!("{}.mobi", name))
+        .output()
+        .context(format!("Failed to execute {}", KINDLEGEN))?;
+
+    let succeeded = kindlegen.status.success() || kindlegen.status.code() == Some(EXIT_WARNINGS);
+    if !succeeded {
+        bail!(
+            "{} exited unsuccessfully:\n{}",
+            KINDLEGEN,
+            errors(&String::from_utf8_lossy(&kindlegen.stdout))
+        );
+    }
+
+    // KindleGen writes next to its input
+    fs::copy(
+        build_dir.join(format!("{}.mobi", name)),
+        output.path(Format::Mobi)
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_lines() {
+        let output = indoc!("
+            Info(prcgen):I1047: Added metadata dc:Title
+            Error(prcgen):E24011: TOC section scope is not included in the parent chapter
+            Info(prcgen):I1037: Mobi file built with WARNINGS!
+            ");
+        assert_eq!(
+            "Error(prcgen):E24011: TOC section scope is not included in the parent chapter",
+            errors(output)
+        );
+    }
+}
@@ -0,0 +1,172 @@
+mod epub;
+mod highlight;
+mod html;
+mod latex;
+mod markup;
+mod mobi;
+mod output;
+mod pandoc;
+mod pdf;
+mod sections;
+mod site;
+mod tables;
+mod template;
+mod theme;
+
+pub use self::markup::{anchors, title_identifier};
+pub use self::output::Output;
+
+use aux::file;
+use failure::{Error, ResultExt};
+use metadata::BookMetadata;
+use prepare::Contents;
+use serde::de::{self, Deserialize, Deserializer};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    path::PathBuf,
+    str::FromStr,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format { Html, Epub, Markdown, Site, Latex, Pdf, Mobi }
+
+impl Format {
+    pub const ALL: &'static [Format] = &[
+        Format::Markdown,
+        Format::Epub,
+        Format::Html,
+        Format::Site,
+        Format::Latex,
+        Format::Pdf,
+        Format::Mobi,
+    ];
+
+    /// What gets rendered unless asked otherwise
+    pub const DEFAULT: &'static [Format] = &[Format::Markdown, Format::Epub, Format::Html];
+
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            Format::Html => "html",
+            Format::Epub => "epub",
+            Format::Markdown => "md",
+            Format::Site => "html",
+            Format::Latex => "tex",
+            Format::Pdf => "pdf",
+            Format::Mobi => "mobi",
+        }
+    }
+
+    /// Width code lines are wrapped at unless configured otherwise,
+    /// e-reader screens are too narrow for long lines
+    pub fn code_width(&self) -> Option<usize> {
+        match self {
+            Format::Epub => Some(80),
+            Format::Mobi => Some(64),
+            _ => None,
+        }
+    }
+
+    /// Highlighting theme unless configured otherwise, dark on screens
+    /// and greyscale on the e-ink screens of Kindles
+    pub fn theme(&self) -> &'static str {
+        match self {
+            Format::Html | Format::Site => "breezedark",
+            Format::Mobi => "monochrome",
+            _ => theme::DEFAULT,
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, mut fmt: &mut Formatter) -> fmt::Result {
+        write!(&mut fmt, "{}", match self {
+            Format::Html => "HTML",
+            Format::Epub => "ePub",
+            Format::Markdown => "Markdown",
+            Format::Site => "Website",
+            Format::Latex => "LaTeX",
+            Format::Pdf => "PDF",
+            Format::Mobi => "Kindle",
+        })
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        match source.to_lowercase().as_str() {
+            "html" => Ok(Format::Html),
+            "epub" => Ok(Format::Epub),
+            "md" | "markdown" => Ok(Format::Markdown),
+            "site" | "website" => Ok(Format::Site),
+            "tex" | "latex" => Ok(Format::Latex),
+            "pdf" => Ok(Format::Pdf),
+            "mobi" | "kindle" => Ok(Format::Mobi),
+            _ => bail!(
+                "Unknown format '{}', expected one of: \
+                 html, epub, markdown, site, latex, pdf, mobi",
+                source
+            ),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Format {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Which implementation turns the aggregated markdown into the output format
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend { Native, Pandoc }
+
+/// Renders `format` and returns the path it was written to
+pub fn to_file(
+    meta: &BookMetadata,
+    body: &str,
+    contents: &[Contents],
+    format: Format,
+    backend: Backend,
+    output: &Output,
+) -> Result<PathBuf, Error> {
+    let path = output.path(format);
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)
+            .context(format!("Failed to create output directory {}", directory.display()))?;
+    }
+
+    match (format, backend) {
+        (Format::Markdown, _) => file::from_string(&path, meta.to_yaml()? + "\n" + body)?,
+
+        // Pandoc has no notion of a multi-page website or of Kindle formats
+        (Format::Site, _) => site::render_to_dir(meta, body, contents, output)?,
+        (Format::Mobi, _) => mobi::render_to_file(meta, body, contents, output)?,
+
+        (Format::Epub, Backend::Native) => epub::render_to_file(meta, body, contents, output)?,
+        (Format::Html, Backend::Native) => html::render_to_file(meta, body, output)?,
+        (Format::Latex, Backend::Native) => latex::render_to_file(meta, body, output)?,
+        (Format::Pdf, Backend::Native) => pdf::render_to_file(meta, body, output)?,
+
+        _ => pandoc::render_to_file(meta, body, format, output)?,
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_from_str() {
+        assert_eq!(Format::Epub, "epub".parse().unwrap());
+        assert_eq!(Format::Markdown, "MD".parse().unwrap());
+        assert_eq!(Format::Mobi, "kindle".parse().unwrap());
+        assert!("docx".parse::<Format>().is_err());
+    }
+}
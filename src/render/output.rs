@@ -0,0 +1,163 @@
+use super::{theme::Theme, Format};
+use aux::file;
+use failure::{Error, ResultExt};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+};
+
+mod reg {
+    pub const PLACEHOLDER: &str = r"\{(?P<key>[^{}]*)\}";
+}
+
+/// `tool_version` is another name for `version`
+const PLACEHOLDERS: &[&str] = &["prefix", "date", "version", "tool_version", "ext"];
+
+/// Where rendered books are written, how they are named and styled
+#[derive(Clone, Debug)]
+pub struct Output {
+    pub directory: PathBuf,
+    pub name: String,
+    pub prefix: String,
+    pub date: String,
+    /// Replaces the default stylesheets of a format
+    pub stylesheets: HashMap<Format, String>,
+    /// Replaces the default code width of a format, 0 turns wrapping off
+    pub code_widths: HashMap<Format, usize>,
+    /// Replaces the default highlighting theme of a format
+    pub themes: HashMap<Format, String>,
+}
+
+impl Output {
+    /// `name` may use the placeholders `{prefix}`, `{date}`, `{version}`
+    /// (of compile-trpl, not of the book, also `{tool_version}`) and `{ext}`
+    pub fn new<P: Into<PathBuf>>(directory: P, name: &str, prefix: &str, date: &str) -> Result<Self, Error> {
+        lazy_static_regex!(PLACEHOLDER, reg::PLACEHOLDER);
+
+        for capture in PLACEHOLDER.captures_iter(name) {
+            if !PLACEHOLDERS.contains(&&capture["key"]) {
+                bail!(
+                    "Unknown placeholder '{}' in output name, expected one of: {{{}}}",
+                    &capture[0],
+                    PLACEHOLDERS.join("}, {")
+                );
+            }
+        }
+
+        Ok(Output {
+            directory: directory.into(),
+            name: name.to_string(),
+            prefix: prefix.to_string(),
+            date: date.to_string(),
+            stylesheets: HashMap::new(),
+            code_widths: HashMap::new(),
+            themes: HashMap::new(),
+        })
+    }
+
+    fn fill(&self, name: &str, extension: &str) -> String {
+        name
+            .replace("{prefix}", &self.prefix)
+            .replace("{date}", &self.date)
+            .replace("{version}", env!("CARGO_PKG_VERSION"))
+            .replace("{tool_version}", env!("CARGO_PKG_VERSION"))
+            .replace("{ext}", extension)
+    }
+
+    /// The file name without extension, also used for the website directory
+    pub fn stem(&self) -> String {
+        self.fill(&self.name.replace(".{ext}", ""), "")
+    }
+
+    /// The stylesheets to use for `format`
+    pub fn stylesheets(&self, format: Format, defaults: &[&str]) -> Vec<PathBuf> {
+        match self.stylesheets.get(&format) {
+            Some(path) => vec![PathBuf::from(path)],
+            None => defaults.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    /// The contents of all stylesheets for `format`
+    pub fn stylesheet(&self, format: Format, defaults: &[&str]) -> Result<String, Error> {
+        let mut stylesheet = String::new();
+        for path in self.stylesheets(format, defaults) {
+            stylesheet.push_str(&file::to_string(&path)
+                .context(format!("Failed to read stylesheet {}", path.display()))?);
+        }
+        Ok(stylesheet)
+    }
+
+    /// The name of the highlighting theme of `format`
+    pub fn theme(&self, format: Format) -> &str {
+        self.themes.get(&format).map_or(format.theme(), String::as_str)
+    }
+
+    /// The style sheet of the highlighting theme of `format`
+    pub fn highlighting(&self, format: Format) -> Result<String, Error> {
+        Ok(Theme::load(self.theme(format))?.css())
+    }
+
+    /// The highlighting theme of `format` as LaTeX definitions
+    pub fn latex_highlighting(&self, format: Format) -> Result<String, Error> {
+        Ok(Theme::load(self.theme(format))?.latex())
+    }
+
+    /// The width code lines of `format` are wrapped at, if any
+    pub fn code_width(&self, format: Format) -> Option<usize> {
+        match self.code_widths.get(&format) {
+            Some(0) => None,
+            Some(width) => Some(*width),
+            None => format.code_width(),
+        }
+    }
+
+    /// The path `format` gets written to
+    pub fn path(&self, format: Format) -> PathBuf {
+        let file_name = match format {
+            Format::Site => self.stem(),
+            _ if self.name.contains("{ext}") => self.fill(&self.name, format.file_extension()),
+            _ => format!("{}.{}", self.stem(), format.file_extension()),
+        };
+        self.directory.join(file_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_paths() {
+        let output = Output::new("out", "{prefix}-{date}.{ext}", "trpl", "2016-10-01").unwrap();
+        assert_eq!(PathBuf::from("out/trpl-2016-10-01.epub"), output.path(Format::Epub));
+        assert_eq!(PathBuf::from("out/trpl-2016-10-01"), output.path(Format::Site));
+
+        let output = Output::new("out", "{prefix}", "trpl", "2016-10-01").unwrap();
+        assert_eq!(PathBuf::from("out/trpl.pdf"), output.path(Format::Pdf));
+
+        assert!(Output::new("out", "{prefix}-{data}.{ext}", "trpl", "2016-10-01").is_err());
+
+        let version = env!("CARGO_PKG_VERSION");
+        let output = Output::new("out", "{prefix}-{date}-{version}.{ext}", "trpl", "2016-10-01").unwrap();
+        assert_eq!(PathBuf::from(format!("out/trpl-2016-10-01-{}.html", version)), output.path(Format::Html));
+        let output = Output::new("out", "{prefix}-{tool_version}.{ext}", "trpl", "2016-10-01").unwrap();
+        assert_eq!(PathBuf::from(format!("out/trpl-{}.html", version)), output.path(Format::Html));
+
+        let mut output = output;
+        output.stylesheets.insert(Format::Html, "custom.css".into());
+        assert_eq!(vec![PathBuf::from("custom.css")], output.stylesheets(Format::Html, &["lib/pandoc.css"]));
+        assert_eq!(vec![PathBuf::from("lib/epub.css")], output.stylesheets(Format::Epub, &["lib/epub.css"]));
+
+        output.code_widths.insert(Format::Epub, 72);
+        output.code_widths.insert(Format::Mobi, 0);
+        assert_eq!(Some(72), output.code_width(Format::Epub));
+        assert_eq!(None, output.code_width(Format::Mobi));
+        assert_eq!(None, output.code_width(Format::Html));
+
+        output.themes.insert(Format::Html, "tango".into());
+        assert_eq!("tango", output.theme(Format::Html));
+        assert_eq!("breezedark", output.theme(Format::Site));
+        assert_eq!("tango", output.theme(Format::Epub));
+        assert_eq!("monochrome", output.theme(Format::Mobi));
+    }
+}
@@ -0,0 +1,80 @@
+use super::{theme, Format, Output};
+use failure::{Error, ResultExt};
+use metadata::BookMetadata;
+use std::{
+    process::{Command, Stdio},
+    io::Write,
+};
+
+const PANDOC: &str = "pandoc";
+
+mod options {
+    pub const TO_ALL : &str = "--standalone --self-contained --table-of-contents";
+    pub const TO_HTML: &str = "--to=html5 --section-divs --template=lib/template.html";
+    pub const TO_EPUB: &str = "--to=epub";
+    pub const TO_TEX : &str = "--to=latex";
+    pub const TO_PDF : &str = "--pdf-engine=xelatex";
+    pub const FROM_MD: &str = indoc!("
+        --from=markdown+grid_tables+pipe_tables-simple_tables+raw_html+implicit\
+        _figures+footnotes+intraword_underscores+auto_identifiers-inline_code\
+        _attributes
+        ");
+}
+
+fn options_for(format: Format) -> &'static str {
+    use self::Format::*;
+    match format {
+        Html => options::TO_HTML,
+        Epub => options::TO_EPUB,
+        Latex => options::TO_TEX,
+        Pdf => options::TO_PDF,
+        Markdown | Site | Mobi => unreachable!(),
+    }
+}
+
+fn stylesheets_for(format: Format) -> &'static [&'static str] {
+    match format {
+        Format::Html => &["lib/pandoc.css"],
+        Format::Epub => &["lib/epub.css"],
+        _ => &[],
+    }
+}
+
+pub fn render_to_file(
+    meta: &BookMetadata,
+    body: &str,
+    format: Format,
+    output: &Output
+) -> Result<(), Error> {
+    let mut child = Command::new(PANDOC)
+        .arg(options::FROM_MD)
+        .args(options::TO_ALL.split(' '))
+        .args(options_for(format).split(' '))
+        .arg(format!("--highlight-style={}", theme::path(output.theme(format)).display()))
+        .args(output
+            .stylesheets(format, stylesheets_for(format))
+            .iter()
+            .map(|path| format!("--css={}", path.display())))
+        .arg(&format!("--output={}", output.path(format).display()))
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to execute pandoc")?;
+
+    if let Some(stdin) = child.stdin.as_mut() {
+        stdin.write_all(meta.to_yaml()?.as_bytes())?;
+        stdin.write_all(body.as_bytes())?;
+    } else {
+        bail!("Failed to get pandoc stdin");
+    }
+
+    if !child.wait()?.success() {
+        bail!("Pandoc exited unsuccessfully.");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+}
@@ -0,0 +1,86 @@
+use super::{latex, Format, Output};
+use aux::file;
+use failure::{Error, ResultExt};
+use metadata::BookMetadata;
+use std::{
+    env,
+    fs,
+    process::{Command, Stdio},
+};
+
+/// `monofont` needs `fontspec`, which needs XeLaTeX or LuaLaTeX
+const ENGINE: &str = "xelatex";
+
+/// Lines of the engine log to show when compilation fails
+const LOG_EXCERPT_LINES: usize = 10;
+
+/// The first LaTeX error in `log` with some context,
+/// or the end of the log if there is no error marker
+fn log_excerpt(log: &str) -> String {
+    let lines: Vec<&str> = log.lines().collect();
+    let start = lines
+        .iter()
+        .position(|line| line.starts_with('!'))
+        .unwrap_or_else(|| lines.len().saturating_sub(LOG_EXCERPT_LINES));
+    let end = (start + LOG_EXCERPT_LINES).min(lines.len());
+
+    lines[start..end].join("\n")
+}
+
+pub fn render_to_file(
+    meta: &BookMetadata,
+    body: &str,
+    output: &Output
+) -> Result<(), Error> {
+    let name = output.stem();
+    let build_dir = env::temp_dir().join(format!("compile-trpl-{}", name));
+    fs::create_dir_all(&build_dir)?;
+
+    let source = build_dir.join(format!("{}.tex", name));
+    file::from_string(&source, latex::render_document(meta, body, &output.latex_highlighting(Format::Pdf)?)?)?;
+
+    // Twice, so the table of contents and references are resolved
+    for _ in 0..2 {
+        let status = Command::new(ENGINE)
+            .arg("-interaction=nonstopmode")
+            .arg("-halt-on-error")
+            .arg(format!("-output-directory={}", build_dir.display()))
+            .arg(&source)
+            .stdout(Stdio::null())
+            .status()
+            .context(format!("Failed to execute {}", ENGINE))?;
+
+        if !status.success() {
+            let log = file::to_string(build_dir.join(format!("{}.log", name)))
+                .unwrap_or_default();
+            bail!("{} exited unsuccessfully:\n{}", ENGINE, log_excerpt(&log));
+        }
+    }
+
+    fs::copy(
+        build_dir.join(format!("{}.pdf", name)),
+        output.path(Format::Pdf)
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_excerpt() {
+        let log = indoc!(r"
+            This is XeTeX, Version 3.14159265
+            (./trpl-2016-10-01.tex
+            ! Undefined control sequence.
+            l.42 \foo
+            ");
+        assert_eq!(
+            "! Undefined control sequence.\nl.42 \\foo",
+            log_excerpt(log)
+        );
+        assert_eq!("No pages of output.", log_excerpt("No pages of output."));
+    }
+}
@@ -0,0 +1,215 @@
+//! Splits the aggregated book back into its chapters.
+//!
+//! `prepare` starts every ToC chapter with a `{#sec--…}` header, so each
+//! section here corresponds to one `toc::Chapter`, plus the introduction,
+//! the `{#part--…}` heading of every part and the `{#book--…}` heading of
+//! every book in an omnibus. Headings inside chapters have ids like
+//! `sec--chapter--heading` and stay in their chapter.
+
+use regex::Captures;
+use std::collections::HashMap;
+use ::prepare::{
+    markdown::{Document, Node},
+    Contents,
+};
+
+mod reg {
+    pub const CHAPTER_HEADER: &str =
+        r"^(?P<title>.+?)\s\{#(?P<id>(?:sec|part|book)--[^}\s]+)\}$";
+    pub const LOCAL_HREF: &str = r##"href="#(?P<id>[^"]+)""##;
+    pub const ID: &str = r#"\sid="(?P<id>[^"]+)""#;
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Section {
+    pub slug: String,
+    pub title: String,
+    pub markdown: String,
+}
+
+impl Section {
+    pub fn file_name(&self, extension: &str) -> String {
+        format!("{}.{}", self.slug, extension)
+    }
+}
+
+/// Part and book ids, and section ids of chapters rather than the headings inside them
+fn is_chapter_id(id: &str) -> bool {
+    id.starts_with("part--") || id.starts_with("book--") || !id.trim_start_matches("sec--").contains("--")
+}
+
+pub fn split(body: &str) -> Vec<Section> {
+    lazy_static_regex!(CHAPTER_HEADER, reg::CHAPTER_HEADER);
+
+    // Everything before the first chapter is the introduction
+    let mut sections = vec![Section {
+        slug: "readme".into(),
+        title: "Introduction".into(),
+        markdown: String::new(),
+    }];
+
+    for node in Document::parse(body).nodes() {
+        if let Node::Heading { title, .. } = node {
+            match CHAPTER_HEADER.captures(title) {
+                Some(ref header) if is_chapter_id(&header["id"]) => sections.push(Section {
+                    slug: header["id"].trim_start_matches("sec--").to_string(),
+                    title: header["title"].to_string(),
+                    markdown: String::new(),
+                }),
+                _ => {},
+            }
+        }
+
+        sections.last_mut().unwrap().markdown.push_str(&node.to_string());
+    }
+
+    if sections[0].markdown.trim().is_empty() { sections.remove(0); }
+    sections
+}
+
+/// Point `#id` links of `(file name, html)` documents at the document
+/// that actually contains `id`
+pub fn link_documents(documents: &mut [(String, String)]) {
+    lazy_static_regex!(LOCAL_HREF, reg::LOCAL_HREF);
+    lazy_static_regex!(ID, reg::ID);
+
+    let locations: HashMap<String, String> = documents
+        .iter()
+        .flat_map(|(file_name, html)| ID
+            .captures_iter(html)
+            .map(move |id| (id["id"].to_string(), file_name.clone()))
+        )
+        .collect();
+
+    for (file_name, html) in documents.iter_mut() {
+        let linked = LOCAL_HREF.replace_all(html, |link: &Captures| {
+            match locations.get(&link["id"]) {
+                Some(target) if target != file_name =>
+                    format!("href=\"{}#{}\"", target, &link["id"]),
+                _ => link[0].to_string(),
+            }
+        }).into_owned();
+        *html = linked;
+    }
+}
+
+pub struct NavPoint<'a> {
+    pub section: &'a Section,
+    pub children: Vec<NavPoint<'a>>,
+}
+
+fn nav_points<'a>(sections: &'a [Section], contents: &[Contents]) -> Vec<NavPoint<'a>> {
+    contents
+        .iter()
+        .flat_map(|chapter| {
+            let children = nav_points(sections, &chapter.children);
+            let slug = chapter.id.trim_start_matches("sec--");
+            match sections.iter().find(|section| section.slug == slug) {
+                Some(section) => vec![NavPoint { section, children }],
+                None => children,
+            }
+        })
+        .collect()
+}
+
+/// Nest sections the way `contents` nests their chapters, the
+/// introduction, which `SUMMARY.md` does not list, comes first
+pub fn nav_tree<'a>(sections: &'a [Section], contents: &[Contents]) -> Vec<NavPoint<'a>> {
+    let mut points = nav_points(sections, contents);
+    if let Some(section) = sections.first().filter(|section| section.slug == "readme") {
+        points.insert(0, NavPoint { section, children: Vec::new() });
+    }
+    points
+}
+
+/// The sections of `points` in reading order
+pub fn reading_order<'a>(points: &[NavPoint<'a>]) -> Vec<&'a Section> {
+    points
+        .iter()
+        .flat_map(|point| Some(point.section).into_iter().chain(reading_order(&point.children)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = indoc!("
+        # Introduction
+
+        Welcome! See [the end](#sec--end).
+
+        # Start {#sec--start}
+
+        ```rust
+        # Not a chapter {#sec--code}
+        ```
+
+        ~~~
+        # Not a chapter either {#sec--tilde}
+        ```
+        ~~~
+
+        ## Middle {#sec--middle}
+
+        ### Details {#sec--middle--details}
+
+        # Part I {#part--part-i}
+
+        # End {#sec--end}
+
+        # The Rustonomicon {#book--nomicon}
+        ");
+
+    #[test]
+    fn sections() {
+        let sections = split(BODY);
+        let outline: Vec<(&str, &str)> = sections
+            .iter()
+            .map(|s| (s.slug.as_str(), s.title.as_str()))
+            .collect();
+
+        assert_eq!(
+            vec![
+                ("readme", "Introduction"),
+                ("start", "Start"),
+                ("middle", "Middle"),
+                ("part--part-i", "Part I"),
+                ("end", "End"),
+                ("book--nomicon", "The Rustonomicon"),
+            ],
+            outline
+        );
+        assert!(sections[2].markdown.contains("### Details"));
+
+        let chapter = |id: &str, children| Contents { id: id.into(), children };
+        let contents = vec![
+            chapter("sec--start", vec![chapter("sec--middle", vec![])]),
+            // A draft, its chapters take its place
+            chapter("part--part-i", vec![chapter("sec--draft", vec![chapter("sec--end", vec![])])]),
+            chapter("book--nomicon", vec![]),
+        ];
+        let points = nav_tree(&sections, &contents);
+        assert_eq!(4, points.len());
+        assert_eq!("middle", points[1].children[0].section.slug);
+        assert_eq!("part--part-i", points[2].section.slug);
+        assert_eq!("end", points[2].children[0].section.slug);
+
+        let order: Vec<&str> = reading_order(&points).iter().map(|section| section.slug.as_str()).collect();
+        assert_eq!(vec!["readme", "start", "middle", "part--part-i", "end", "book--nomicon"], order);
+    }
+
+    #[test]
+    fn cross_document_links() {
+        let mut documents = vec![
+            ("a.xhtml".to_string(), r##"<a href="#sec--b">B</a> <a href="#x">X</a><p id="x"/>"##.to_string()),
+            ("b.xhtml".to_string(), r##"<h1 id="sec--b">B</h1>"##.to_string()),
+        ];
+        link_documents(&mut documents);
+
+        assert_eq!(
+            r##"<a href="b.xhtml#sec--b">B</a> <a href="#x">X</a><p id="x"/>"##,
+            documents[0].1
+        );
+    }
+}
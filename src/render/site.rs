@@ -0,0 +1,135 @@
+use super::{
+    markup::{escape, Renderer},
+    sections::{self, NavPoint, Section},
+    template::{self, Context},
+    Format,
+    Output,
+};
+use aux::file;
+use metadata::BookMetadata;
+use prepare::Contents;
+use failure::{Error, ResultExt};
+use std::fs;
+
+const TEMPLATE: &str = "lib/site.html";
+const STYLESHEETS: &[&str] = &["lib/pandoc.css", "lib/site.css"];
+const EXTENSION: &str = "html";
+
+fn sidebar(points: &[NavPoint], current: Option<&str>, output: &mut String) {
+    output.push_str("<ol>\n");
+    for point in points {
+        let is_current = current == Some(point.section.slug.as_str());
+        output.push_str(&format!(
+            "<li><a href=\"{}\"{}>{}</a>",
+            point.section.file_name(EXTENSION),
+            if is_current { " class=\"active\"" } else { "" },
+            escape(&point.section.title),
+        ));
+        if !point.children.is_empty() { sidebar(&point.children, current, output) }
+        output.push_str("</li>\n");
+    }
+    output.push_str("</ol>\n");
+}
+
+fn page_context(base: &Context, points: &[NavPoint], current: Option<&Section>) -> Context {
+    let mut navigation = String::new();
+    sidebar(points, current.map(|section| section.slug.as_str()), &mut navigation);
+
+    let mut context = base.clone();
+    context.set("sidebar", navigation);
+    if let Some(section) = current {
+        context.set("pagetitle", escape(&section.title));
+    }
+    context
+}
+
+fn link_neighbour(context: &mut Context, key: &str, neighbour: Option<&Section>) {
+    if let Some(section) = neighbour {
+        context.set(key, section.file_name(EXTENSION));
+        context.set(&format!("{}-title", key), escape(&section.title));
+    }
+}
+
+/// Renders one page per chapter into a directory named after the output stem
+pub fn render_to_dir(
+    meta: &BookMetadata,
+    body: &str,
+    contents: &[Contents],
+    output: &Output
+) -> Result<(), Error> {
+    let sections = sections::split(body);
+
+    let mut renderer = Renderer::new();
+    let mut documents: Vec<(String, String)> = sections
+        .iter()
+        .map(|section| (section.file_name(EXTENSION), renderer.render(&section.markdown)))
+        .collect();
+    sections::link_documents(&mut documents);
+
+    let template = file::to_string(TEMPLATE).context("Failed to read website template")?;
+    let mut stylesheet = output.stylesheet(Format::Site, STYLESHEETS)?;
+    stylesheet.push_str(&output.highlighting(Format::Site)?);
+
+    let directory = output.path(Format::Site);
+    fs::create_dir_all(&directory)?;
+    file::from_string(directory.join("style.css"), stylesheet)?;
+
+    let points = sections::nav_tree(&sections, contents);
+    let order = sections::reading_order(&points);
+    let mut base = Context::new();
+    base
+        .set("locale", escape(&meta.language))
+        .set("title", escape(&meta.title));
+
+    let mut index = page_context(&base, &points, None);
+    link_neighbour(&mut index, "next", order.first().cloned());
+    index.set("body", format!(
+        "<h1 class=\"title\">{}</h1>\n<p class=\"author\">{}</p>\n<p>{}</p>\n",
+        escape(&meta.title),
+        escape(&meta.author()),
+        escape(&meta.description),
+    ));
+    file::from_string(directory.join("index.html"), template::render(&template, &index)?)?;
+
+    for (position, (file_name, html)) in documents.into_iter().enumerate() {
+        let section = &sections[position];
+        let mut page = page_context(&base, &points, Some(section));
+        // Sections missing from the navigation have no neighbours
+        let place = order.iter().position(|other| other.slug == section.slug);
+        link_neighbour(&mut page, "previous", place.and_then(|p| p.checked_sub(1)).map(|p| order[p]));
+        link_neighbour(&mut page, "next", place.and_then(|p| order.get(p + 1).cloned()));
+        page.set("body", html);
+
+        file::from_string(directory.join(file_name), template::render(&template, &page)?)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn active_sidebar_entry() {
+        let sections = sections::split("# A {#sec--a}\n## B {#sec--b}\n");
+        let contents = vec![Contents {
+            id: "sec--a".into(),
+            children: vec![Contents { id: "sec--b".into(), children: Vec::new() }],
+        }];
+        let mut output = String::new();
+        sidebar(&sections::nav_tree(&sections, &contents), Some("b"), &mut output);
+
+        assert_eq!(
+            indoc!(r#"
+                <ol>
+                <li><a href="a.html">A</a><ol>
+                <li><a href="b.html" class="active">B</a></li>
+                </ol>
+                </li>
+                </ol>
+                "#),
+            output
+        );
+    }
+}
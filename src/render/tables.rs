@@ -0,0 +1,165 @@
+//! Pandoc `grid_tables`, which CommonMark doesn't know about.
+//! They are turned into HTML blocks before the markdown is parsed.
+
+mod reg {
+    pub const BORDER: &str = r"^\+([-=]+\+)+$";
+}
+
+struct Table {
+    head: Vec<Vec<String>>,
+    body: Vec<Vec<String>>,
+}
+
+/// Char indices of the column separators in a `+---+----+` border
+fn columns(border: &[char]) -> Vec<usize> {
+    border
+        .iter()
+        .enumerate()
+        .filter(|&(_, chr)| *chr == '+')
+        .map(|(index, _)| index)
+        .collect()
+}
+
+fn parse(lines: &[&str]) -> Option<Table> {
+    let first: Vec<char> = lines.first()?.chars().collect();
+    let columns = columns(&first);
+
+    let mut table = Table { head: Vec::new(), body: Vec::new() };
+    let mut row = vec![String::new(); columns.len() - 1];
+
+    for line in &lines[1..] {
+        let chars: Vec<char> = line.chars().collect();
+
+        if chars.first() == Some(&'+') {
+            if columns != self::columns(&chars) { return None }
+
+            table.body.push(row);
+            row = vec![String::new(); columns.len() - 1];
+
+            if chars.contains(&'=') {
+                // Header separator: everything so far was the header
+                table.head.append(&mut table.body);
+            }
+        } else {
+            for (cell, bounds) in row.iter_mut().zip(columns.windows(2)) {
+                let end = bounds[1].min(chars.len());
+                let start = (bounds[0] + 1).min(end);
+                let text: String = chars[start..end].iter().collect();
+                cell.push_str(text.trim());
+                cell.push('\n');
+            }
+        }
+    }
+
+    Some(table)
+}
+
+fn render_row<F>(row: &[String], cell_tag: &str, render_cell: &mut F, output: &mut String)
+where
+    F: FnMut(&str) -> String
+{
+    output.push_str("<tr>");
+    for cell in row {
+        let html = render_cell(cell);
+        let html = html.trim();
+        let html = if html.starts_with("<p>") && html.ends_with("</p>") && html.matches("<p>").count() == 1 {
+            &html[3..html.len() - 4]
+        } else {
+            html
+        };
+        output.push_str(&format!("<{tag}>{}</{tag}>", html.replace('\n', " "), tag = cell_tag));
+    }
+    output.push_str("</tr>");
+}
+
+/// Replace every grid table outside of code blocks with an HTML table,
+/// cell contents are rendered with `render_cell`
+pub fn replace_grid_tables<F>(markdown: &str, mut render_cell: F) -> String
+where
+    F: FnMut(&str) -> String
+{
+    lazy_static_regex!(BORDER, reg::BORDER);
+
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut output = String::with_capacity(markdown.len());
+    let mut in_code_block = false;
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+        if line.starts_with("```") { in_code_block = !in_code_block }
+
+        if !in_code_block && BORDER.is_match(line) {
+            let length = lines[index..]
+                .iter()
+                .take_while(|l| l.starts_with('+') || l.starts_with('|'))
+                .count();
+            let block = &lines[index..index + length];
+
+            if let Some(table) = if BORDER.is_match(block[length - 1]) { parse(block) } else { None } {
+                output.push_str("<table>");
+                if !table.head.is_empty() {
+                    output.push_str("<thead>");
+                    for row in &table.head { render_row(row, "th", &mut render_cell, &mut output) }
+                    output.push_str("</thead>");
+                }
+                output.push_str("<tbody>");
+                for row in &table.body { render_row(row, "td", &mut render_cell, &mut output) }
+                output.push_str("</tbody></table>\n\n");
+
+                index += length;
+                continue;
+            }
+        }
+
+        output.push_str(line);
+        output.push('\n');
+        index += 1;
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRID_TABLE: &str = indoc!("
+        Text
+
+        +-------+---------+
+        | Name  | Meaning |
+        +=======+=========+
+        | `u8`  | byte    |
+        |       | sized   |
+        +-------+---------+
+
+        ```text
+        +---+
+        | a |
+        +---+
+        ```
+        ");
+
+    #[test]
+    fn grid_table() {
+        let replaced = replace_grid_tables(GRID_TABLE, |cell| format!("<p>{}</p>", cell.trim()));
+
+        assert_eq!(
+            indoc!("
+                Text
+
+                <table><thead><tr><th>Name</th><th>Meaning</th></tr></thead>\
+                <tbody><tr><td>`u8`</td><td>byte sized</td></tr></tbody></table>
+
+
+                ```text
+                +---+
+                | a |
+                +---+
+                ```
+                "),
+            replaced
+        );
+    }
+}
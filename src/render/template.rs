@@ -0,0 +1,141 @@
+//! Just enough of Pandoc's template language to fill `lib/template.html`:
+//! `$var$`, `$if(var)$…$else$…$endif$`, `$for(var)$…$endfor$` and `$$`.
+
+use failure::Error;
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Text(String),
+    Variable(String),
+    If(String, Vec<Node>, Vec<Node>),
+    For(String, Vec<Node>),
+}
+
+/// Template variables, every variable may hold several values for `$for$`
+#[derive(Clone, Debug, Default)]
+pub struct Context(HashMap<String, Vec<String>>);
+
+impl Context {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn set<V: Into<String>>(&mut self, key: &str, value: V) -> &mut Self {
+        self.0.insert(key.to_string(), vec![value.into()]);
+        self
+    }
+
+    fn values(&self, key: &str) -> &[String] {
+        self.0.get(key).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    fn is_set(&self, key: &str) -> bool {
+        self.values(key).iter().any(|value| !value.is_empty())
+    }
+}
+
+fn argument<'a>(directive: &'a str, keyword: &str) -> Option<&'a str> {
+    if directive.starts_with(keyword) && directive.ends_with(')') {
+        Some(&directive[keyword.len()..directive.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// Parses nodes until one of `terminators` and returns it alongside
+fn parse<'a, I>(parts: &mut I, terminators: &[&str]) -> Result<(Vec<Node>, Option<&'a str>), Error>
+where
+    I: Iterator<Item = (usize, &'a str)>
+{
+    let mut nodes = Vec::new();
+
+    while let Some((index, part)) = parts.next() {
+        // Every other part sits between a pair of `$`
+        if index % 2 == 0 {
+            if !part.is_empty() { nodes.push(Node::Text(part.to_string())) }
+            continue;
+        }
+
+        if terminators.contains(&part) { return Ok((nodes, Some(part))) }
+
+        if part.is_empty() {
+            nodes.push(Node::Text("$".into()));
+        } else if let Some(variable) = argument(part, "if(") {
+            let (then, end) = parse(parts, &["else", "endif"])?;
+            let otherwise = match end {
+                Some("else") => parse(parts, &["endif"])?.0,
+                Some(_) => Vec::new(),
+                None => bail!("Unterminated $if({})$ in template", variable),
+            };
+            nodes.push(Node::If(variable.to_string(), then, otherwise));
+        } else if let Some(variable) = argument(part, "for(") {
+            match parse(parts, &["endfor"])? {
+                (body, Some(_)) => nodes.push(Node::For(variable.to_string(), body)),
+                (_, None) => bail!("Unterminated $for({})$ in template", variable),
+            }
+        } else if ["else", "endif", "endfor"].contains(&part) {
+            bail!("Unexpected ${}$ in template", part);
+        } else {
+            nodes.push(Node::Variable(part.to_string()));
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+fn fill(nodes: &[Node], context: &Context, output: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Variable(variable) => {
+                for value in context.values(variable) { output.push_str(value) }
+            }
+            Node::If(variable, then, otherwise) => if context.is_set(variable) {
+                fill(then, context, output)
+            } else {
+                fill(otherwise, context, output)
+            },
+            Node::For(variable, body) => for value in context.values(variable) {
+                let mut scope = context.clone();
+                scope.set(variable, value.as_str());
+                fill(body, &scope, output);
+            },
+        }
+    }
+}
+
+pub fn render(template: &str, context: &Context) -> Result<String, Error> {
+    let (nodes, _) = parse(&mut template.split('$').enumerate(), &[])?;
+
+    let mut output = String::with_capacity(template.len());
+    fill(&nodes, context, &mut output);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = indoc!("
+        <title>$title$</title>$if(date)$ ($date$)$else$ (undated)$endif$
+        $for(author)$<p>$author$</p>$endfor$ 5$$
+        ");
+
+    #[test]
+    fn fill_template() {
+        let mut context = Context::new();
+        context
+            .set("title", "Book")
+            .set("author", "A");
+
+        assert_eq!(
+            "<title>Book</title> (undated)\n<p>A</p> 5$\n",
+            render(TEMPLATE, &context).unwrap()
+        );
+    }
+
+    #[test]
+    fn unterminated() {
+        assert!(render("$if(x)$ no end", &Context::new()).is_err());
+        assert!(render("$endfor$", &Context::new()).is_err());
+    }
+}
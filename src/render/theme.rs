@@ -0,0 +1,219 @@
+//! Highlighting themes in Pandoc's JSON format, as printed by
+//! `pandoc --print-highlight-style`. They live in `lib/{name}.theme`, so
+//! Pandoc and the native renderers share the same ones.
+
+use aux::file;
+use failure::Error;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+};
+
+/// The theme of formats that have none configured
+pub const DEFAULT: &str = "tango";
+
+/// Pandoc's token types and the classes their spans get, in Pandoc's order
+const TOKENS: &[(&str, &str)] = &[
+    ("Keyword", "kw"), ("DataType", "dt"), ("DecVal", "dv"), ("BaseN", "bn"),
+    ("Float", "fl"), ("Constant", "cn"), ("Char", "ch"), ("SpecialChar", "sc"),
+    ("String", "st"), ("VerbatimString", "vs"), ("SpecialString", "ss"),
+    ("Import", "im"), ("Comment", "co"), ("Documentation", "do"),
+    ("Annotation", "an"), ("CommentVar", "cv"), ("Other", "ot"),
+    ("Function", "fu"), ("Variable", "va"), ("ControlFlow", "cf"),
+    ("Operator", "op"), ("BuiltIn", "bu"), ("Extension", "ex"),
+    ("Preprocessor", "pp"), ("Attribute", "at"), ("RegionMarker", "re"),
+    ("Information", "in"), ("Warning", "wa"), ("Alert", "al"), ("Error", "er"),
+];
+
+/// The token type of the class `class`, for LaTeX's `\{type}Tok` macros
+pub fn token(class: &str) -> Option<&'static str> {
+    TOKENS.iter().find(|(_, other)| *other == class).map(|(token, _)| *token)
+}
+
+/// A `#rrggbb` colour in the form of xcolor's `HTML` model
+fn latex_color(color: &str) -> String {
+    color.trim_start_matches('#').to_uppercase()
+}
+
+/// The file the theme called `name` is read from
+pub fn path(name: &str) -> PathBuf {
+    PathBuf::from(format!("lib/{}.theme", name))
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+struct TextStyle {
+    #[serde(default)]
+    text_color: Option<String>,
+    #[serde(default)]
+    background_color: Option<String>,
+    #[serde(default)]
+    bold: bool,
+    #[serde(default)]
+    italic: bool,
+    #[serde(default)]
+    underline: bool,
+}
+
+impl TextStyle {
+    fn declarations(&self) -> String {
+        let mut declarations = String::new();
+        if let Some(ref color) = self.text_color {
+            declarations.push_str(&format!(" color: {};", color));
+        }
+        if let Some(ref color) = self.background_color {
+            declarations.push_str(&format!(" background-color: {};", color));
+        }
+        if self.bold { declarations.push_str(" font-weight: bold;") }
+        if self.italic { declarations.push_str(" font-style: italic;") }
+        if self.underline { declarations.push_str(" text-decoration: underline;") }
+        declarations
+    }
+
+    /// The body of a LaTeX macro applying the style to its argument
+    fn latex(&self) -> String {
+        let mut text = String::from("#1");
+        if self.bold { text = format!("\\textbf{{{}}}", text) }
+        if self.italic { text = format!("\\textit{{{}}}", text) }
+        if self.underline { text = format!("\\underline{{{}}}", text) }
+        if let Some(ref color) = self.text_color {
+            text = format!("\\textcolor[HTML]{{{}}}{{{}}}", latex_color(color), text);
+        }
+        if let Some(ref color) = self.background_color {
+            text = format!("\\colorbox[HTML]{{{}}}{{{}}}", latex_color(color), text);
+        }
+        text
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Theme {
+    #[serde(default)]
+    text_color: Option<String>,
+    #[serde(default)]
+    background_color: Option<String>,
+    #[serde(default)]
+    text_styles: HashMap<String, TextStyle>,
+}
+
+impl Theme {
+    pub fn load(name: &str) -> Result<Self, Error> {
+        let path = path(name);
+        let source = file::to_string(&path)
+            .map_err(|e| format_err!("Failed to read highlighting theme {}: {}", path.display(), e))?;
+
+        // JSON is YAML too
+        ::serde_yaml::from_str(&source)
+            .map_err(|e| format_err!("Invalid highlighting theme {}: {}", path.display(), e))
+    }
+
+    /// The theme as a style sheet for the markup of `highlight`, like Pandoc's
+    pub fn css(&self) -> String {
+        let block = TextStyle {
+            text_color: self.text_color.clone(),
+            background_color: self.background_color.clone(),
+            ..TextStyle::default()
+        };
+
+        let mut css = String::from("div.sourceCode { overflow-x: auto; }\n");
+        css.push_str(&format!("pre.sourceCode {{ margin: 0;{} }}\n", block.declarations()));
+
+        for (token, class) in TOKENS {
+            let declarations = self.text_styles.get(*token).map(TextStyle::declarations).unwrap_or_default();
+            if !declarations.is_empty() {
+                css.push_str(&format!("code > span.{} {{{} }} /* {} */\n", class, declarations, token));
+            }
+        }
+
+        css
+    }
+
+    /// The theme as LaTeX definitions for the markup of `latex_code_block`,
+    /// like Pandoc's: `Shaded` and `Highlighting` environments and a
+    /// `\{type}Tok` macro for every token type
+    pub fn latex(&self) -> String {
+        let mut options = String::from("commandchars=\\\\\\{\\}");
+        if let Some(ref color) = self.text_color {
+            options.push_str(&format!(",formatcom=\\color[HTML]{{{}}}", latex_color(color)));
+        }
+
+        let mut latex = format!("\\DefineVerbatimEnvironment{{Highlighting}}{{Verbatim}}{{{}}}\n", options);
+        match self.background_color {
+            Some(ref color) => latex.push_str(&format!(
+                "\\usepackage{{framed}}\n\\definecolor{{shadecolor}}{{HTML}}{{{}}}\n\
+                 \\newenvironment{{Shaded}}{{\\begin{{snugshade}}}}{{\\end{{snugshade}}}}\n",
+                latex_color(color)
+            )),
+            None => latex.push_str("\\newenvironment{Shaded}{}{}\n"),
+        }
+
+        for (token, _) in TOKENS {
+            let style = self.text_styles.get(*token).map_or_else(|| "#1".to_string(), TextStyle::latex);
+            latex.push_str(&format!("\\newcommand{{\\{}Tok}}[1]{{{}}}\n", token, style));
+        }
+
+        latex
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn theme_css() {
+        let theme: Theme = ::serde_yaml::from_str(r##"{
+            "text-color": null,
+            "background-color": "#f8f8f8",
+            "line-number-color": "#aaaaaa",
+            "text-styles": {
+                "Keyword": { "text-color": "#204a87", "background-color": null, "bold": true, "italic": false, "underline": false },
+                "Comment": { "text-color": "#8f5902", "italic": true },
+                "Other": { "text-color": null }
+            }
+        }"##).unwrap();
+
+        assert_eq!(
+            indoc!("
+                div.sourceCode { overflow-x: auto; }
+                pre.sourceCode { margin: 0; background-color: #f8f8f8; }
+                code > span.kw { color: #204a87; font-weight: bold; } /* Keyword */
+                code > span.co { color: #8f5902; font-style: italic; } /* Comment */
+                "),
+            theme.css()
+        );
+    }
+
+    #[test]
+    fn theme_latex() {
+        let theme: Theme = ::serde_yaml::from_str(r##"{
+            "text-color": "#cfcfc2",
+            "background-color": "#232629",
+            "text-styles": {
+                "Keyword": { "text-color": "#cfcfc2", "bold": true },
+                "Comment": { "text-color": "#7a7c7d", "italic": true }
+            }
+        }"##).unwrap();
+        let latex = theme.latex();
+
+        assert!(latex.starts_with(indoc!(r"
+            \DefineVerbatimEnvironment{Highlighting}{Verbatim}{commandchars=\\\{\},formatcom=\color[HTML]{CFCFC2}}
+            \usepackage{framed}
+            \definecolor{shadecolor}{HTML}{232629}
+            \newenvironment{Shaded}{\begin{snugshade}}{\end{snugshade}}
+            \newcommand{\KeywordTok}[1]{\textcolor[HTML]{CFCFC2}{\textbf{#1}}}
+            \newcommand{\DataTypeTok}[1]{#1}
+            ")));
+        assert!(latex.contains("\\newcommand{\\CommentTok}[1]{\\textcolor[HTML]{7A7C7D}{\\textit{#1}}}\n"));
+        assert_eq!(Some("Keyword"), token("kw"));
+    }
+
+    #[test]
+    fn bundled_themes() {
+        for name in &[DEFAULT, "breezedark", "monochrome"] {
+            assert!(Theme::load(name).is_ok(), "{} does not load", name);
+        }
+        assert!(Theme::load("missing").is_err());
+    }
+}